@@ -0,0 +1,249 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{config::CONFIG, router::HttpResponse};
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{HeaderMap, AUTHORIZATION},
+        StatusCode,
+    },
+    middleware::Next,
+    Error, HttpResponse as ActixHttpResponse,
+};
+use once_cell::sync::Lazy;
+
+/// Paths served without a token
+const OPEN_PATHS: [&str; 3] = ["/api/healthz", "/api/readyz", "/api/openapi.json"];
+
+/// Tokens accepted by the api, `ZO_REPORT_SERVER_API_TOKENS` and `ZO_INTERNAL_GRPC_TOKEN`
+pub static API_TOKENS: Lazy<Vec<String>> =
+    Lazy::new(|| parse_tokens(&CONFIG.auth.api_tokens, &CONFIG.grpc.internal_grpc_token));
+
+fn parse_tokens(api_tokens: &str, internal_grpc_token: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = api_tokens
+        .split(',')
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+        .collect();
+    if !internal_grpc_token.is_empty() {
+        tokens.push(internal_grpc_token.to_string());
+    }
+    tokens
+}
+
+/// Compares in constant time, so the time taken does not reveal how much of a token matched
+pub(crate) fn token_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Reads the token of `Authorization: Bearer <token>`, or of the bare shared secret
+/// `Authorization: <token>`
+fn request_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?.trim();
+    match value.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => Some(token.trim()),
+        Some(_) => None,
+        None => Some(value),
+    }
+}
+
+/// Checks whether the request carries one of the accepted tokens
+pub fn is_authorized(headers: &HeaderMap) -> bool {
+    has_token(headers, &API_TOKENS)
+}
+
+fn has_token(headers: &HeaderMap, tokens: &[String]) -> bool {
+    let Some(token) = request_token(headers) else {
+        return false;
+    };
+    tokens.iter().fold(false, |found, t| {
+        token_eq(t.as_bytes(), token.as_bytes()) | found
+    })
+}
+
+/// Only the exact open paths are served without a token
+fn is_open(path: &str) -> bool {
+    OPEN_PATHS.contains(&path)
+}
+
+/// Checks whether the request carries one of the api tokens or the `ZO_METRICS_TOKEN`
+fn is_authorized_for_metrics(headers: &HeaderMap) -> bool {
+    let metrics_token = &CONFIG.auth.metrics_token;
+    let is_metrics_token = !metrics_token.is_empty()
+        && request_token(headers)
+            .is_some_and(|token| token_eq(token.as_bytes(), metrics_token.as_bytes()));
+    is_metrics_token | is_authorized(headers)
+}
+
+/// Middleware rejecting api requests without an accepted token
+pub async fn check_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    if is_open(req.path()) || is_authorized(req.headers()) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+    Ok(unauthorized(req))
+}
+
+/// Middleware rejecting metrics requests without an api token or the metrics token, the
+/// metrics are labeled by org and would list every tenant
+pub async fn check_metrics_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    if is_authorized_for_metrics(req.headers()) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+    Ok(unauthorized(req))
+}
+
+fn unauthorized<B>(req: ServiceRequest) -> ServiceResponse<EitherBody<B>> {
+    log::warn!(
+        "Unauthorized request to {} from {}",
+        req.path(),
+        req.connection_info()
+            .realip_remote_addr()
+            .unwrap_or("unknown")
+    );
+    let resp = ActixHttpResponse::Unauthorized().json(HttpResponse::new(
+        "Missing or invalid api token".to_string(),
+        StatusCode::UNAUTHORIZED.into(),
+    ));
+    req.into_response(resp).map_into_right_body()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::header::HeaderValue, middleware::from_fn, web, App, HttpResponse};
+
+    fn headers(authorization: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = authorization {
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn tokens() -> Vec<String> {
+        parse_tokens(" api-1, ,api-2 ", "grpc-secret")
+    }
+
+    #[test]
+    fn tokens_are_parsed() {
+        assert_eq!(tokens(), ["api-1", "api-2", "grpc-secret"]);
+        assert!(parse_tokens("", "").is_empty());
+    }
+
+    #[test]
+    fn bearer_and_bare_tokens_are_read() {
+        assert_eq!(request_token(&headers(Some("Bearer api-1"))), Some("api-1"));
+        assert_eq!(
+            request_token(&headers(Some("bearer  api-1 "))),
+            Some("api-1")
+        );
+        assert_eq!(request_token(&headers(Some("api-1"))), Some("api-1"));
+        assert_eq!(request_token(&headers(Some("Basic YTpi"))), None);
+        assert_eq!(request_token(&headers(None)), None);
+    }
+
+    #[test]
+    fn accepted_tokens_are_authorized() {
+        let tokens = tokens();
+        assert!(has_token(&headers(Some("Bearer api-1")), &tokens));
+        assert!(has_token(&headers(Some("api-2")), &tokens));
+        // The internal gRPC token is accepted by the http api as well
+        assert!(has_token(&headers(Some("Bearer grpc-secret")), &tokens));
+    }
+
+    #[test]
+    fn other_tokens_are_rejected() {
+        let tokens = tokens();
+        for authorization in [
+            Some("Bearer api-3"),
+            Some("Bearer api-"),
+            Some("Bearer api-11"),
+            Some("Bearer "),
+            Some(""),
+            Some("Basic api-1"),
+            None,
+        ] {
+            assert!(
+                !has_token(&headers(authorization), &tokens),
+                "{authorization:?}"
+            );
+        }
+        // Without configured tokens nothing is authorized, not even an empty token
+        assert!(!has_token(&headers(Some("")), &[]));
+    }
+
+    #[test]
+    fn tokens_of_other_lengths_differ() {
+        assert!(token_eq(b"secret", b"secret"));
+        assert!(!token_eq(b"secret", b"secre"));
+        assert!(!token_eq(b"secret", b"secrets"));
+        assert!(!token_eq(b"", b"secret"));
+        assert!(!token_eq(b"secret", b"secreT"));
+    }
+
+    #[test]
+    fn only_exact_open_paths_are_open() {
+        assert!(is_open("/api/healthz"));
+        assert!(is_open("/api/readyz"));
+        assert!(is_open("/api/openapi.json"));
+        for path in [
+            "/api/healthz/../x",
+            "/api/healthz/",
+            "/api/healthzx",
+            "/api/jobs/1",
+            "/API/healthz",
+        ] {
+            assert!(!is_open(path), "{path}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn requests_without_a_token_only_reach_open_paths() {
+        let app = actix_web::test::init_service(
+            App::new().service(
+                web::scope("/api")
+                    .wrap(from_fn(check_token))
+                    .default_service(web::to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+        for (path, status) in [
+            ("/api/healthz", StatusCode::OK),
+            ("/api/healthz/../x", StatusCode::UNAUTHORIZED),
+            ("/api/jobs/1", StatusCode::UNAUTHORIZED),
+        ] {
+            let req = actix_web::test::TestRequest::get().uri(path).to_request();
+            let resp = actix_web::test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status, "{path}");
+        }
+    }
+}
@@ -15,18 +15,49 @@
 
 mod utils;
 
+use crate::{
+    openapi,
+    outbox::{self, OutboxState},
+};
+
 pub async fn cli() -> Result<bool, anyhow::Error> {
     let app = clap::Command::new("report-generator")
         .version(crate::config::VERSION)
         .about(clap::crate_description!())
-        .subcommands(&[clap::Command::new("init-dir")
-            .about("init report-generator data dir")
-            .arg(
-                clap::Arg::new("path")
-                    .short('p')
-                    .long("path")
-                    .help("init this path as data root dir"),
-            )])
+        .subcommands(&[
+            clap::Command::new("init-dir")
+                .about("init report-generator data dir")
+                .arg(
+                    clap::Arg::new("path")
+                        .short('p')
+                        .long("path")
+                        .help("init this path as data root dir"),
+                ),
+            clap::Command::new("openapi").about("print the OpenAPI document of the http api"),
+            clap::Command::new("outbox")
+                .about("inspect and replay the email outbox")
+                .subcommand_required(true)
+                .subcommands(&[
+                    clap::Command::new("list").about("list outbox entries").arg(
+                        clap::Arg::new("state")
+                            .short('s')
+                            .long("state")
+                            .help("only list entries in this state: pending, sent or failed"),
+                    ),
+                    clap::Command::new("show")
+                        .about("show an outbox entry")
+                        .arg(clap::Arg::new("id").required(true).help("outbox entry id")),
+                    clap::Command::new("replay")
+                        .about("deliver an outbox entry again to the recipients which did not receive it")
+                        .arg(clap::Arg::new("id").required(true).help("outbox entry id"))
+                        .arg(
+                            clap::Arg::new("all")
+                                .long("all")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("deliver to all recipients"),
+                        ),
+                ]),
+        ])
         .get_matches();
 
     if app.subcommand().is_none() {
@@ -47,6 +78,51 @@ pub async fn cli() -> Result<bool, anyhow::Error> {
         return Ok(true);
     }
 
+    if name == "openapi" {
+        println!("{}", *openapi::SPEC);
+        return Ok(true);
+    }
+
+    if name == "outbox" {
+        let (name, command) = command.subcommand().unwrap();
+        match name {
+            "list" => {
+                let state = command
+                    .get_one::<String>("state")
+                    .map(|s| s.parse::<OutboxState>())
+                    .transpose()?;
+                for entry in outbox::list(state).await? {
+                    println!(
+                        "{}\t{:?}\t{}/{}\tattempts: {}\tpending: {}",
+                        entry.id,
+                        entry.state,
+                        entry.org_id,
+                        entry.report_name,
+                        entry.attempts,
+                        entry.pending.len()
+                    );
+                }
+            }
+            "show" => {
+                let id = command.get_one::<String>("id").unwrap();
+                match outbox::get(id).await? {
+                    Some(entry) => println!("{}", serde_json::to_string_pretty(&entry)?),
+                    None => return Err(anyhow::anyhow!("outbox entry {id} not found")),
+                }
+            }
+            "replay" => {
+                let id = command.get_one::<String>("id").unwrap();
+                let all = command.get_flag("all");
+                match outbox::replay(id, all).await? {
+                    Some(entry) => println!("{}", serde_json::to_string_pretty(&entry)?),
+                    None => return Err(anyhow::anyhow!("outbox entry {id} not found")),
+                }
+            }
+            _ => unreachable!(),
+        }
+        return Ok(true);
+    }
+
     println!("command {name} execute successfully");
     Ok(true)
 }
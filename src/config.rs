@@ -13,7 +13,10 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::ReportAttachmentDimensions;
+use crate::{
+    oauth2::{OAuth2Config, TokenProvider},
+    ReportAttachmentDimensions,
+};
 use chromiumoxide::{
     browser::{BrowserConfig, BrowserConfigBuilder},
     detection::{default_executable, DetectionOptions},
@@ -24,15 +27,21 @@ use dotenv_config::EnvConfig;
 use dotenvy::dotenv;
 use lettre::{
     transport::smtp::{
-        authentication::Credentials,
+        authentication::{Credentials, Mechanism},
         client::{Tls, TlsParameters},
     },
     AsyncSmtpTransport, Tokio1Executor,
 };
 use once_cell::sync::Lazy;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Timezone of the dashboards of reports sent without a timezone
+pub const DEFAULT_TIMEZONE: &str = "Europe/London";
+
 pub static CONFIG: Lazy<Config> = Lazy::new(init);
 
 static CHROME_LAUNCHER_OPTIONS: tokio::sync::OnceCell<BrowserConfigBuilder> =
@@ -46,6 +55,15 @@ pub struct Config {
     pub common: Common,
     // pub limit: Limit,
     pub smtp: Smtp,
+    pub outbox: Outbox,
+    pub jobs: Jobs,
+    pub scheduler: Scheduler,
+    pub render: Render,
+    pub webhook: Webhook,
+    pub tracing: Tracing,
+    pub readiness: Readiness,
+    pub idempotency: Idempotency,
+    pub limits: Limits,
     pub chrome: Chrome,
     pub tokio_console: TokioConsole,
 }
@@ -179,6 +197,318 @@ pub struct Smtp {
     pub smtp_from_email: String,
     #[env_config(name = "ZO_SMTP_ENCRYPTION", default = "")]
     pub smtp_encryption: String,
+    #[env_config(
+        name = "ZO_MAIL_TRANSPORT",
+        default = "smtp",
+        help = "How report emails are delivered: smtp, file to write .eml files to ZO_MAIL_FILE_DIR, or stdout"
+    )]
+    pub mail_transport: String,
+    #[env_config(
+        name = "ZO_MAIL_FILE_DIR",
+        default = "./data/mail",
+        help = "Directory of the .eml files written by the file mail transport"
+    )]
+    pub mail_file_dir: String,
+    #[env_config(
+        name = "ZO_SMTP_OAUTH2_TOKEN_URL",
+        default = "",
+        help = "OAuth2 token endpoint. When set, SMTP authenticates with XOAUTH2 as ZO_SMTP_USER_NAME instead of the password"
+    )]
+    pub smtp_oauth2_token_url: String,
+    #[env_config(name = "ZO_SMTP_OAUTH2_CLIENT_ID", default = "")]
+    pub smtp_oauth2_client_id: String,
+    #[env_config(name = "ZO_SMTP_OAUTH2_CLIENT_SECRET", default = "")]
+    pub smtp_oauth2_client_secret: String,
+    #[env_config(
+        name = "ZO_SMTP_OAUTH2_REFRESH_TOKEN",
+        default = "",
+        help = "Uses the refresh-token flow when set, else the client-credentials flow"
+    )]
+    pub smtp_oauth2_refresh_token: String,
+    #[env_config(
+        name = "ZO_SMTP_OAUTH2_SCOPE",
+        default = "",
+        help = "Space separated scopes requested with the token, e.g. https://outlook.office365.com/.default"
+    )]
+    pub smtp_oauth2_scope: String,
+    #[env_config(
+        name = "ZO_SMTP_PROFILES_FILE",
+        default = "",
+        help = "Path to a JSON file with named SMTP profiles and the org to profile mapping"
+    )]
+    pub smtp_profiles_file: String,
+    #[env_config(
+        name = "ZO_SMTP_MAX_RETRIES",
+        default = 3,
+        help = "Number of retries for transient SMTP errors, per recipient"
+    )]
+    pub smtp_max_retries: u32,
+    #[env_config(
+        name = "ZO_SMTP_RETRY_INITIAL_BACKOFF_MS",
+        default = 1000,
+        help = "Wait before the first SMTP retry in milliseconds, doubled after every retry"
+    )]
+    pub smtp_retry_initial_backoff_ms: u64,
+    #[env_config(
+        name = "ZO_SMTP_RETRY_MAX_BACKOFF_MS",
+        default = 30000,
+        help = "Maximum wait between SMTP retries in milliseconds"
+    )]
+    pub smtp_retry_max_backoff_ms: u64,
+    #[env_config(
+        name = "ZO_SMTP_DKIM_SELECTOR",
+        default = "",
+        help = "DKIM selector. Emails are DKIM signed when the selector, domain and private key path are set"
+    )]
+    pub dkim_selector: String,
+    #[env_config(
+        name = "ZO_SMTP_DKIM_DOMAIN",
+        default = "",
+        help = "DKIM signing domain"
+    )]
+    pub dkim_domain: String,
+    #[env_config(
+        name = "ZO_SMTP_DKIM_PRIVATE_KEY_PATH",
+        default = "",
+        help = "Path to the DKIM private key, PKCS#1 PEM for rsa or base64 for ed25519"
+    )]
+    pub dkim_private_key_path: String,
+    #[env_config(
+        name = "ZO_SMTP_DKIM_ALGORITHM",
+        default = "rsa",
+        help = "DKIM signing algorithm, rsa or ed25519"
+    )]
+    pub dkim_algorithm: String,
+    #[env_config(
+        name = "ZO_SMTP_DKIM_HEADERS",
+        default = "From,Subject,To,Date",
+        help = "Comma separated list of the headers included in the DKIM signature"
+    )]
+    pub dkim_headers: String,
+    #[env_config(
+        name = "ZO_EMAIL_TEMPLATE_FILE",
+        default = "",
+        help = "Path to a JSON file with the default email subject, html_body and text_body templates"
+    )]
+    pub email_template_file: String,
+}
+
+#[derive(EnvConfig)]
+pub struct Render {
+    #[env_config(
+        name = "ZO_RENDER_MAX_CONCURRENCY",
+        default = 2,
+        help = "Maximum number of reports rendered at once, each render runs its own browser"
+    )]
+    pub max_concurrency: usize,
+    #[env_config(
+        name = "ZO_RENDER_QUEUE_SIZE",
+        default = 50,
+        help = "Maximum number of reports waiting for a render slot, requests beyond it get a 503"
+    )]
+    pub queue_size: usize,
+    #[env_config(
+        name = "ZO_RENDER_RETRY_AFTER_SECS",
+        default = 30,
+        help = "Retry-After returned when the render queue is full"
+    )]
+    pub retry_after_secs: u64,
+}
+
+#[derive(EnvConfig)]
+pub struct Readiness {
+    #[env_config(
+        name = "ZO_READYZ_CACHE_SECS",
+        default = 30,
+        help = "Interval of the readiness checks in seconds, /readyz returns the last result"
+    )]
+    pub cache_secs: u64,
+    #[env_config(
+        name = "ZO_READYZ_TIMEOUT_SECS",
+        default = 10,
+        help = "Timeout of each readiness check in seconds"
+    )]
+    pub timeout_secs: u64,
+}
+
+#[derive(EnvConfig)]
+pub struct Idempotency {
+    #[env_config(
+        name = "ZO_IDEMPOTENCY_WINDOW_SECS",
+        default = 3600,
+        help = "How long the result of a send request is returned again for the same idempotency key, 0 disables idempotency keys"
+    )]
+    pub window_secs: u64,
+}
+
+#[derive(EnvConfig)]
+pub struct Limits {
+    #[env_config(
+        name = "ZO_LIMIT_RENDERS",
+        default = 0,
+        help = "Reports an org can send per ZO_LIMIT_RENDER_WINDOW_SECS, 0 is unlimited"
+    )]
+    pub renders: u64,
+    #[env_config(
+        name = "ZO_LIMIT_RENDER_WINDOW_SECS",
+        default = 3600,
+        help = "Window of ZO_LIMIT_RENDERS in seconds"
+    )]
+    pub render_window_secs: u64,
+    #[env_config(
+        name = "ZO_LIMIT_EMAILS_PER_DAY",
+        default = 0,
+        help = "Report emails an org can send per day, 0 is unlimited"
+    )]
+    pub emails_per_day: u64,
+    #[env_config(
+        name = "ZO_LIMIT_RECIPIENTS_PER_DAY",
+        default = 0,
+        help = "Report email recipients of an org per day, 0 is unlimited"
+    )]
+    pub recipients_per_day: u64,
+    #[env_config(
+        name = "ZO_LIMIT_ATTACHMENT_BYTES_PER_DAY",
+        default = 0,
+        help = "Bytes of report attachments an org can email per day, 0 is unlimited"
+    )]
+    pub attachment_bytes_per_day: u64,
+    #[env_config(
+        name = "ZO_LIMITS_FILE",
+        default = "",
+        help = "Path to a JSON file with the limits of single orgs"
+    )]
+    pub limits_file: String,
+}
+
+#[derive(EnvConfig)]
+pub struct Tracing {
+    #[env_config(
+        name = "ZO_TRACING_ENABLED",
+        default = false,
+        help = "Export traces of the report runs to the OTLP collector"
+    )]
+    pub enabled: bool,
+    #[env_config(
+        name = "ZO_OTEL_OTLP_URL",
+        default = "http://127.0.0.1:5080/api/default/v1/traces",
+        help = "OTLP http endpoint the traces are exported to"
+    )]
+    pub otlp_url: String,
+    #[env_config(name = "ZO_TRACING_HEADER_KEY", default = "Authorization")]
+    pub header_key: String,
+    #[env_config(name = "ZO_TRACING_HEADER_VALUE", default = "")]
+    pub header_value: String,
+}
+
+#[derive(EnvConfig)]
+pub struct Webhook {
+    #[env_config(
+        name = "ZO_WEBHOOK_TIMEOUT_SECS",
+        default = 10,
+        help = "Timeout of a report callback request in seconds"
+    )]
+    pub timeout_secs: u64,
+    #[env_config(
+        name = "ZO_WEBHOOK_MAX_RETRIES",
+        default = 3,
+        help = "Number of retries of a failed report callback"
+    )]
+    pub max_retries: u32,
+    #[env_config(
+        name = "ZO_WEBHOOK_ALLOWED_URLS",
+        default = "",
+        help = "Comma separated url prefixes report callbacks may be posted to, by default any public http(s) url"
+    )]
+    pub allowed_urls: String,
+}
+
+#[derive(EnvConfig)]
+pub struct Jobs {
+    #[env_config(
+        name = "ZO_JOBS_PERSIST",
+        default = false,
+        help = "Persist async report jobs and their artifacts to ZO_JOBS_DIR, else they are kept in memory"
+    )]
+    pub persist: bool,
+    #[env_config(name = "ZO_JOBS_DIR", default = "./data/jobs")]
+    pub dir: String,
+    #[env_config(
+        name = "ZO_JOBS_RETENTION_HOURS",
+        default = 24,
+        help = "Hours after which finished jobs and their artifacts are removed"
+    )]
+    pub retention_hours: u64,
+}
+
+#[derive(EnvConfig)]
+pub struct Scheduler {
+    #[env_config(
+        name = "ZO_SCHEDULER_ENABLED",
+        default = false,
+        help = "Run the reports defined in ZO_SCHEDULER_PATH on their cron schedule"
+    )]
+    pub enabled: bool,
+    #[env_config(
+        name = "ZO_SCHEDULER_PATH",
+        default = "./data/schedules",
+        help = "JSON file or directory of JSON files with the scheduled report definitions"
+    )]
+    pub path: String,
+    #[env_config(
+        name = "ZO_SCHEDULER_STATE_DIR",
+        default = "./data/scheduler",
+        help = "Directory where the last run of each scheduled report is stored"
+    )]
+    pub state_dir: String,
+    #[env_config(
+        name = "ZO_SCHEDULER_MISFIRE_POLICY",
+        default = "run_once",
+        help = "run_once runs a report which missed its schedules once, skip waits for its next schedule"
+    )]
+    pub misfire_policy: String,
+    #[env_config(
+        name = "ZO_SCHEDULER_MISFIRE_GRACE_SECS",
+        default = 300,
+        help = "A run later than this many seconds after its schedule is a misfire"
+    )]
+    pub misfire_grace_secs: u64,
+}
+
+#[derive(EnvConfig)]
+pub struct Outbox {
+    #[env_config(
+        name = "ZO_OUTBOX_ENABLED",
+        default = false,
+        help = "Store report emails on disk before sending and retry them in the background"
+    )]
+    pub enabled: bool,
+    #[env_config(name = "ZO_OUTBOX_DIR", default = "./data/outbox")]
+    pub dir: String,
+    #[env_config(
+        name = "ZO_OUTBOX_POLL_INTERVAL_SECS",
+        default = 30,
+        help = "Interval in seconds at which pending outbox entries are retried"
+    )]
+    pub poll_interval_secs: u64,
+    #[env_config(
+        name = "ZO_OUTBOX_MAX_ATTEMPTS",
+        default = 10,
+        help = "Delivery rounds after which a pending outbox entry is marked as failed"
+    )]
+    pub max_attempts: u32,
+    #[env_config(
+        name = "ZO_OUTBOX_RETRY_BACKOFF_SECS",
+        default = 60,
+        help = "Wait before the first outbox retry in seconds, doubled after every round up to 1 hour"
+    )]
+    pub retry_backoff_secs: u64,
+    #[env_config(
+        name = "ZO_OUTBOX_RETENTION_HOURS",
+        default = 168,
+        help = "Hours after which sent and failed outbox entries are removed"
+    )]
+    pub retention_hours: u64,
 }
 
 #[derive(EnvConfig)]
@@ -187,6 +517,18 @@ pub struct Auth {
     pub user_email: String,
     #[env_config(name = "ZO_REPORT_USER_PASSWORD", default = "")]
     pub user_password: String,
+    #[env_config(
+        name = "ZO_REPORT_SERVER_API_TOKENS",
+        default = "",
+        help = "Comma separated tokens accepted by the report server api, several tokens allow key rotation"
+    )]
+    pub api_tokens: String,
+    #[env_config(
+        name = "ZO_METRICS_TOKEN",
+        default = "",
+        help = "Token accepted on /metrics next to the api tokens, so scrapers need no api token"
+    )]
+    pub metrics_token: String,
 }
 
 #[derive(EnvConfig)]
@@ -223,6 +565,18 @@ pub struct Common {
     pub o2_web_uri: String,
     #[env_config(name = "ZO_LOCAL_MODE", default = true)]
     pub local_mode: bool,
+    #[env_config(
+        name = "ZO_O2_ALLOWED_URLS",
+        default = "",
+        help = "Comma separated O2 web urls the report server may log into, defaults to ZO_O2_APP_URL"
+    )]
+    pub o2_allowed_urls: String,
+    #[env_config(
+        name = "ZO_O2_PUBLIC_URL",
+        default = "",
+        help = "O2 web url of the dashboard links in report emails, defaults to ZO_O2_APP_URL"
+    )]
+    pub o2_public_url: String,
 }
 
 pub fn init() -> Config {
@@ -230,6 +584,66 @@ pub fn init() -> Config {
     Config::init().unwrap()
 }
 
+/// O2 web urls the browser may log into with the report user
+pub static O2_ALLOWED_URLS: Lazy<Vec<Url>> = Lazy::new(|| {
+    let urls = if CONFIG.common.o2_allowed_urls.trim().is_empty() {
+        CONFIG.common.o2_web_uri.as_str()
+    } else {
+        CONFIG.common.o2_allowed_urls.as_str()
+    };
+    parse_o2_urls(urls).unwrap_or_else(|e| panic!("{e}"))
+});
+
+/// Parses a comma separated list of O2 web urls
+fn parse_o2_urls(urls: &str) -> Result<Vec<Url>, anyhow::Error> {
+    urls.split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(parse_o2_url)
+        .collect()
+}
+
+fn parse_o2_url(url: &str) -> Result<Url, anyhow::Error> {
+    let mut parsed = Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid O2 url {url}: {e}"))?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host().is_none() {
+        return Err(anyhow::anyhow!(
+            "Invalid O2 url {url}: must be an http(s) url"
+        ));
+    }
+    let path = parsed.path().trim_end_matches('/').to_string();
+    parsed.set_path(&path);
+    parsed.set_query(None);
+    parsed.set_fragment(None);
+    Ok(parsed)
+}
+
+/// Checks that the O2 web url is one of `ZO_O2_ALLOWED_URLS`, so the report user credentials
+/// are only ever entered into a trusted O2
+pub fn check_o2_url(url: &str) -> Result<(), anyhow::Error> {
+    check_o2_url_in(url, &O2_ALLOWED_URLS)
+}
+
+fn check_o2_url_in(url: &str, allowed: &[Url]) -> Result<(), anyhow::Error> {
+    let parsed = parse_o2_url(url)?;
+    if allowed.contains(&parsed) {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("O2 url {url} is not allowed"))
+    }
+}
+
+/// Base of the dashboard links sent to the users. `ZO_O2_APP_URL` is often an internal
+/// address, so links to dashboards rendered from it use `ZO_O2_PUBLIC_URL` when it is set.
+pub fn o2_link_url(web_url: &str) -> &str {
+    let public_url = CONFIG.common.o2_public_url.trim();
+    let web_url = web_url.trim_end_matches('/');
+    if !public_url.is_empty() && web_url == CONFIG.common.o2_web_uri.trim_end_matches('/') {
+        public_url.trim_end_matches('/')
+    } else {
+        web_url
+    }
+}
+
 pub async fn get_chrome_launch_options(
     report_attachment_dimensions: ReportAttachmentDimensions,
 ) -> &'static BrowserConfigBuilder {
@@ -320,26 +734,246 @@ async fn init_chrome_launch_options(
     browser_config
 }
 
-pub static SMTP_CLIENT: Lazy<AsyncSmtpTransport<Tokio1Executor>> = Lazy::new(|| {
-    let tls_parameters = TlsParameters::new(CONFIG.smtp.smtp_host.clone()).unwrap();
+/// Name of the profile built from the `ZO_SMTP_*` envs
+pub const DEFAULT_SMTP_PROFILE: &str = "default";
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+/// A named SMTP relay and sender
+#[derive(Deserialize, Debug, Clone)]
+pub struct SmtpProfile {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    /// `starttls`, `ssltls` or empty for no encryption
+    #[serde(default)]
+    pub encryption: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    pub from_email: String,
+    #[serde(default)]
+    pub reply_to: String,
+    /// Authenticates with XOAUTH2 as `username` instead of the password
+    #[serde(default)]
+    pub oauth2: Option<OAuth2Config>,
+}
+
+impl SmtpProfile {
+    fn from_env() -> Self {
+        let smtp = &CONFIG.smtp;
+        let oauth2 = (!smtp.smtp_oauth2_token_url.is_empty()).then(|| OAuth2Config {
+            token_url: smtp.smtp_oauth2_token_url.clone(),
+            client_id: smtp.smtp_oauth2_client_id.clone(),
+            client_secret: smtp.smtp_oauth2_client_secret.clone(),
+            refresh_token: smtp.smtp_oauth2_refresh_token.clone(),
+            scope: smtp.smtp_oauth2_scope.clone(),
+        });
+        Self {
+            host: smtp.smtp_host.clone(),
+            port: smtp.smtp_port,
+            encryption: smtp.smtp_encryption.clone(),
+            username: smtp.smtp_username.clone(),
+            password: smtp.smtp_password.clone(),
+            from_email: smtp.smtp_from_email.clone(),
+            reply_to: smtp.smtp_reply_to.clone(),
+            oauth2,
+        }
+    }
+}
+
+/// Content of `ZO_SMTP_PROFILES_FILE`
+#[derive(Deserialize, Debug, Default)]
+pub struct SmtpProfilesFile {
+    #[serde(default)]
+    pub profiles: HashMap<String, SmtpProfile>,
+    /// org_id -> profile name, orgs which are not listed use the default profile
+    #[serde(default)]
+    pub orgs: HashMap<String, String>,
+}
+
+enum SmtpTransport {
+    Basic(AsyncSmtpTransport<Tokio1Executor>),
+    /// The transport is rebuilt with the new credentials whenever the access token changes
+    OAuth2 {
+        tokens: Box<TokenProvider>,
+        current: tokio::sync::Mutex<Option<(String, AsyncSmtpTransport<Tokio1Executor>)>>,
+    },
+}
+
+/// SMTP profiles with one pooled transport per profile, including the default profile
+pub struct SmtpProfiles {
+    pub profiles: HashMap<String, SmtpProfile>,
+    pub orgs: HashMap<String, String>,
+    transports: HashMap<String, SmtpTransport>,
+}
+
+impl SmtpProfiles {
+    /// Transport of the profile. For XOAUTH2 profiles the access token is refreshed
+    /// first when it is about to expire.
+    pub async fn transport(
+        &self,
+        name: &str,
+    ) -> Result<AsyncSmtpTransport<Tokio1Executor>, anyhow::Error> {
+        let (Some(profile), Some(transport)) = (self.profiles.get(name), self.transports.get(name))
+        else {
+            return Err(anyhow::anyhow!("Unknown SMTP profile {name}"));
+        };
+        match transport {
+            SmtpTransport::Basic(transport) => Ok(transport.clone()),
+            SmtpTransport::OAuth2 { tokens, current } => {
+                let token = tokens.access_token().await.map_err(|e| {
+                    anyhow::anyhow!("Error getting access token for SMTP profile {name}: {e}")
+                })?;
+                let mut current = current.lock().await;
+                match &*current {
+                    Some((current_token, transport)) if *current_token == token => {
+                        Ok(transport.clone())
+                    }
+                    _ => {
+                        let transport = build_smtp_transport(profile, Some(&token));
+                        *current = Some((token, transport.clone()));
+                        Ok(transport)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// SMTP profiles from `ZO_SMTP_PROFILES_FILE` and the default profile of the `ZO_SMTP_*` settings
+pub static SMTP_PROFILES: Lazy<SmtpProfiles> = Lazy::new(|| {
+    let path = &CONFIG.smtp.smtp_profiles_file;
+    let mut file = if path.is_empty() {
+        SmtpProfilesFile::default()
+    } else {
+        let content = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Error reading SMTP profiles file {path}: {e}"));
+        serde_json::from_str(&content)
+            .unwrap_or_else(|e| panic!("Error parsing SMTP profiles file {path}: {e}"))
+    };
+
+    if file.profiles.contains_key(DEFAULT_SMTP_PROFILE) {
+        panic!("SMTP profile name {DEFAULT_SMTP_PROFILE} is reserved for the ZO_SMTP_* settings");
+    }
+    for (org_id, profile) in file.orgs.iter() {
+        if profile != DEFAULT_SMTP_PROFILE && !file.profiles.contains_key(profile) {
+            panic!("Unknown SMTP profile {profile} for org {org_id}");
+        }
+    }
+    file.profiles
+        .insert(DEFAULT_SMTP_PROFILE.to_string(), SmtpProfile::from_env());
+
+    let transports = file
+        .profiles
+        .iter()
+        .map(|(name, p)| {
+            let transport = match &p.oauth2 {
+                Some(oauth2) => {
+                    if p.username.is_empty() || oauth2.token_url.is_empty() || oauth2.client_id.is_empty() {
+                        panic!("SMTP profile {name} uses OAuth2 and needs a username, token_url and client_id");
+                    }
+                    SmtpTransport::OAuth2 {
+                        tokens: Box::new(TokenProvider::new(oauth2.clone())),
+                        current: tokio::sync::Mutex::new(None),
+                    }
+                }
+                None => SmtpTransport::Basic(build_smtp_transport(p, None)),
+            };
+            (name.clone(), transport)
+        })
+        .collect();
+    SmtpProfiles {
+        profiles: file.profiles,
+        orgs: file.orgs,
+        transports,
+    }
+});
+
+/// Builds a pooled transport for the profile, authenticating with XOAUTH2 when an
+/// access token is given and with the profile password otherwise
+fn build_smtp_transport(
+    profile: &SmtpProfile,
+    oauth2_token: Option<&str>,
+) -> AsyncSmtpTransport<Tokio1Executor> {
+    let host = profile.host.as_str();
+    let tls_parameters = TlsParameters::new(host.to_string()).unwrap();
     let mut transport_builder =
-        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&CONFIG.smtp.smtp_host)
-            .port(CONFIG.smtp.smtp_port);
+        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host).port(profile.port);
 
-    let option = &CONFIG.smtp.smtp_encryption;
-    transport_builder = if option == "starttls" {
+    transport_builder = if profile.encryption == "starttls" {
         transport_builder.tls(Tls::Required(tls_parameters))
-    } else if option == "ssltls" {
+    } else if profile.encryption == "ssltls" {
         transport_builder.tls(Tls::Wrapper(tls_parameters))
     } else {
         transport_builder
     };
 
-    if !CONFIG.smtp.smtp_username.is_empty() && !CONFIG.smtp.smtp_password.is_empty() {
+    if let Some(token) = oauth2_token {
+        transport_builder = transport_builder
+            .credentials(Credentials::new(
+                profile.username.clone(),
+                token.to_string(),
+            ))
+            .authentication(vec![Mechanism::Xoauth2]);
+    } else if !profile.username.is_empty() && !profile.password.is_empty() {
         transport_builder = transport_builder.credentials(Credentials::new(
-            CONFIG.smtp.smtp_username.clone(),
-            CONFIG.smtp.smtp_password.clone(),
+            profile.username.clone(),
+            profile.password.clone(),
         ));
     }
     transport_builder.build()
-});
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed() -> Vec<Url> {
+        parse_o2_urls("https://o2.example.com/web/, http://o2.internal:5080/web").unwrap()
+    }
+
+    #[test]
+    fn allowed_o2_urls_are_accepted() {
+        let allowed = allowed();
+        for url in [
+            "https://o2.example.com/web",
+            "https://o2.example.com/web/",
+            "https://o2.example.com:443/web",
+            "https://O2.example.com/web?org_identifier=default",
+            "http://o2.internal:5080/web",
+        ] {
+            assert!(check_o2_url_in(url, &allowed).is_ok(), "{url}");
+        }
+    }
+
+    #[test]
+    fn other_o2_urls_are_denied() {
+        let allowed = allowed();
+        for url in [
+            "http://o2.example.com/web",
+            "https://o2.example.com:8443/web",
+            "https://o2.example.com/",
+            "https://o2.example.com/web/dashboards",
+            "https://o2.example.com.evil.com/web",
+            "https://user@o2.example.com/web",
+            "http://o2.internal/web",
+            "http://o2.internal:5081/web",
+            "file:///web",
+            "not a url",
+        ] {
+            assert!(check_o2_url_in(url, &allowed).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn o2_url_lists_are_parsed() {
+        assert_eq!(allowed().len(), 2);
+        assert!(parse_o2_urls(" , ").unwrap().is_empty());
+        assert!(parse_o2_urls("https://o2.example.com, ftp://o2.example.com").is_err());
+        assert!(parse_o2_urls("o2.example.com").is_err());
+    }
+}
@@ -0,0 +1,528 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::config::{CONFIG, SMTP_PROFILES};
+use lettre::{
+    address::{Address, Envelope},
+    message::{
+        dkim::{DkimCanonicalization, DkimConfig, DkimSigningAlgorithm, DkimSigningKey},
+        header::HeaderName,
+        Mailbox,
+    },
+    transport::smtp,
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+use utoipa::ToSchema;
+
+/// DKIM signing configuration, `None` when DKIM is not configured
+pub static DKIM_CONFIG: Lazy<Option<DkimConfig>> =
+    Lazy::new(|| init_dkim_config().unwrap_or_else(|e| panic!("Invalid DKIM configuration: {e}")));
+
+fn init_dkim_config() -> Result<Option<DkimConfig>, anyhow::Error> {
+    let smtp = &CONFIG.smtp;
+    let settings = [
+        &smtp.dkim_selector,
+        &smtp.dkim_domain,
+        &smtp.dkim_private_key_path,
+    ];
+    if settings.iter().all(|s| s.is_empty()) {
+        return Ok(None);
+    }
+    if settings.iter().any(|s| s.is_empty()) {
+        return Err(anyhow::anyhow!(
+            "ZO_SMTP_DKIM_SELECTOR, ZO_SMTP_DKIM_DOMAIN and ZO_SMTP_DKIM_PRIVATE_KEY_PATH must all be set"
+        ));
+    }
+
+    let algorithm = match smtp.dkim_algorithm.to_lowercase().as_str() {
+        "rsa" => DkimSigningAlgorithm::Rsa,
+        "ed25519" => DkimSigningAlgorithm::Ed25519,
+        other => return Err(anyhow::anyhow!("Unknown DKIM algorithm {other}")),
+    };
+    let private_key = std::fs::read_to_string(&smtp.dkim_private_key_path).map_err(|e| {
+        anyhow::anyhow!(
+            "Error reading DKIM private key {}: {e}",
+            smtp.dkim_private_key_path
+        )
+    })?;
+    let private_key = DkimSigningKey::new(private_key.trim(), algorithm)
+        .map_err(|e| anyhow::anyhow!("Error loading DKIM private key: {e}"))?;
+
+    let mut headers = vec![];
+    for header in smtp.dkim_headers.split(',').map(str::trim) {
+        if header.is_empty() {
+            continue;
+        }
+        headers.push(
+            HeaderName::new_from_ascii(header.to_string())
+                .map_err(|e| anyhow::anyhow!("Invalid DKIM header {header}: {e}"))?,
+        );
+    }
+    if headers.is_empty() {
+        return Err(anyhow::anyhow!("ZO_SMTP_DKIM_HEADERS must not be empty"));
+    }
+
+    for (name, profile) in SMTP_PROFILES.profiles.iter() {
+        if !is_dkim_domain(&profile.from_email, &smtp.dkim_domain) {
+            log::warn!(
+                "DKIM domain {} does not match the from email {} of SMTP profile {name}, its emails are not signed",
+                smtp.dkim_domain,
+                profile.from_email
+            );
+        }
+    }
+    log::info!(
+        "DKIM signing enabled for domain {} with selector {}",
+        smtp.dkim_domain,
+        smtp.dkim_selector
+    );
+    Ok(Some(DkimConfig::new(
+        smtp.dkim_selector.clone(),
+        smtp.dkim_domain.clone(),
+        private_key,
+        headers,
+        DkimCanonicalization::default(),
+    )))
+}
+
+/// DKIM configuration for an email from the given address. Emails from another domain than
+/// `ZO_SMTP_DKIM_DOMAIN`, e.g. of an SMTP profile, are not signed, as their signature would
+/// not align with the from address under DMARC.
+pub fn dkim_config_for(from: &str) -> Option<&'static DkimConfig> {
+    let config = DKIM_CONFIG.as_ref()?;
+    is_dkim_domain(from, &CONFIG.smtp.dkim_domain).then_some(config)
+}
+
+fn is_dkim_domain(from: &str, dkim_domain: &str) -> bool {
+    from.parse::<Mailbox>()
+        .is_ok_and(|mailbox| mailbox.email.domain().eq_ignore_ascii_case(dkim_domain))
+}
+
+/// Where report emails go, from `ZO_MAIL_TRANSPORT`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MailTransport {
+    Smtp,
+    /// Writes every email as an `.eml` file to `ZO_MAIL_FILE_DIR`
+    File,
+    /// Writes every email to stdout
+    Stdout,
+}
+
+/// Transport of the report emails from `ZO_MAIL_TRANSPORT`
+pub static MAIL_TRANSPORT: Lazy<MailTransport> =
+    Lazy::new(
+        || match CONFIG.smtp.mail_transport.to_lowercase().as_str() {
+            "smtp" | "" => MailTransport::Smtp,
+            "file" => MailTransport::File,
+            "stdout" => MailTransport::Stdout,
+            other => panic!("Unknown mail transport {other}, expected smtp, file or stdout"),
+        },
+    );
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Sent,
+    Failed,
+    /// Failed with a transient error, delivery is retried from the outbox
+    Queued,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Queued => "queued",
+        }
+    }
+}
+
+/// Delivery result of the report email for a single recipient
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct RecipientStatus {
+    pub recipient: String,
+    pub status: DeliveryStatus,
+    /// Number of SMTP attempts made for this recipient
+    pub attempts: u32,
+    /// SMTP reply code of the last failed attempt, e.g. `550`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RecipientStatus {
+    pub fn failed(recipient: &str, attempts: u32, error: String) -> Self {
+        Self {
+            recipient: recipient.to_string(),
+            status: DeliveryStatus::Failed,
+            attempts,
+            smtp_code: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// Transient SMTP failures (4xx replies, dropped connections, timeouts) are retried.
+/// Permanent 5xx rejections, TLS and client errors are not.
+fn is_retryable(e: &smtp::Error) -> bool {
+    !(e.is_permanent() || e.is_client() || e.is_tls())
+}
+
+/// Delivers the formatted email separately to every recipient of the envelope, so the
+/// headers stay the same for everyone while failures are tracked per recipient.
+pub async fn deliver(
+    client: &AsyncSmtpTransport<Tokio1Executor>,
+    envelope: &Envelope,
+    email: &[u8],
+) -> Vec<RecipientStatus> {
+    let mut results = Vec::with_capacity(envelope.to().len());
+    for recipient in envelope.to() {
+        let (status, _) = deliver_to(client, envelope.from().cloned(), recipient, email).await;
+        results.push(status);
+    }
+    results
+}
+
+/// Delivers the email to one recipient, retrying transient errors.
+/// Also returns whether the last error, if any, was transient.
+pub(crate) async fn deliver_to(
+    client: &AsyncSmtpTransport<Tokio1Executor>,
+    from: Option<Address>,
+    recipient: &Address,
+    email: &[u8],
+) -> (RecipientStatus, bool) {
+    let envelope = match Envelope::new(from, vec![recipient.clone()]) {
+        Ok(envelope) => envelope,
+        Err(e) => {
+            return (
+                RecipientStatus::failed(recipient.as_ref(), 0, e.to_string()),
+                false,
+            )
+        }
+    };
+
+    let max_attempts = CONFIG.smtp.smtp_max_retries + 1;
+    let mut backoff = Duration::from_millis(CONFIG.smtp.smtp_retry_initial_backoff_ms);
+    let max_backoff = Duration::from_millis(CONFIG.smtp.smtp_retry_max_backoff_ms);
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match client.send_raw(&envelope, email).await {
+            Ok(_) => {
+                let status = RecipientStatus {
+                    recipient: recipient.to_string(),
+                    status: DeliveryStatus::Sent,
+                    attempts,
+                    smtp_code: None,
+                    error: None,
+                };
+                return (status, false);
+            }
+            Err(e) if attempts < max_attempts && is_retryable(&e) => {
+                log::warn!(
+                    "Transient error sending email to {recipient} (attempt {attempts}/{max_attempts}), retrying in {}ms: {e}",
+                    backoff.as_millis()
+                );
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(max_backoff);
+            }
+            Err(e) => {
+                log::error!("Error sending email to {recipient} after {attempts} attempts: {e}");
+                let status = RecipientStatus {
+                    recipient: recipient.to_string(),
+                    status: DeliveryStatus::Failed,
+                    attempts,
+                    smtp_code: e.status().map(|code| code.to_string()),
+                    error: Some(e.to_string()),
+                };
+                return (status, is_retryable(&e));
+            }
+        }
+    }
+}
+
+/// Writes the formatted email to `ZO_MAIL_FILE_DIR` instead of sending it and
+/// returns the path of the `.eml` file
+pub async fn write_eml(email: &[u8]) -> Result<String, anyhow::Error> {
+    tokio::fs::create_dir_all(&CONFIG.smtp.mail_file_dir).await?;
+    let path = std::path::Path::new(&CONFIG.smtp.mail_file_dir)
+        .join(format!("{}.eml", uuid::Uuid::now_v7()));
+    tokio::fs::write(&path, email).await?;
+    Ok(path.display().to_string())
+}
+
+/// Writes the formatted email to stdout instead of sending it
+pub fn print_eml(email: &[u8]) -> Result<(), anyhow::Error> {
+    use std::io::Write;
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(email)?;
+    stdout.write_all(b"\n")?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Every recipient of the envelope marked as sent, for the file and stdout transports
+pub fn written_to(envelope: &Envelope) -> Vec<RecipientStatus> {
+    envelope
+        .to()
+        .iter()
+        .map(|recipient| RecipientStatus {
+            recipient: recipient.to_string(),
+            status: DeliveryStatus::Sent,
+            attempts: 1,
+            smtp_code: None,
+            error: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        build_email, template::TemplateVars, EmailAttachmentType, EmailDetails, ReportType,
+        SmtpConfig,
+    };
+    use lettre::transport::smtp::client::{Tls, TlsParameters};
+    use std::sync::{Arc, Mutex};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    fn transport(port: u16) -> AsyncSmtpTransport<Tokio1Executor> {
+        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+            .port(port)
+            .build()
+    }
+
+    /// Local SMTP server, see [`serve`]. Returns a transport for it and the recipients it
+    /// received emails for.
+    pub(crate) async fn smtp_server(
+    ) -> (AsyncSmtpTransport<Tokio1Executor>, Arc<Mutex<Vec<String>>>) {
+        let (port, received) = serve().await;
+        (transport(port), received)
+    }
+
+    /// Starts a local SMTP server which rejects recipients containing `rejected` with 550,
+    /// defers those containing `busy` with 451 and accepts the others. Returns its port and
+    /// the recipients it received emails for.
+    async fn serve() -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(vec![]));
+        let server_received = received.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let received = server_received.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    write.write_all(b"220 localhost\r\n").await.unwrap();
+                    let mut recipients = vec![];
+                    let mut in_data = false;
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let line = line.trim_end();
+                        let reply: &[u8] = if in_data {
+                            if line != "." {
+                                continue;
+                            }
+                            in_data = false;
+                            received.lock().unwrap().append(&mut recipients);
+                            b"250 queued\r\n"
+                        } else if let Some(to) = line.strip_prefix("RCPT TO:") {
+                            if to.contains("rejected") {
+                                b"550 no such user\r\n"
+                            } else if to.contains("busy") {
+                                b"451 try again later\r\n"
+                            } else {
+                                recipients.push(to.trim_matches(['<', '>', ' ']).to_string());
+                                b"250 ok\r\n"
+                            }
+                        } else if line == "DATA" {
+                            in_data = true;
+                            b"354 go ahead\r\n"
+                        } else if line == "QUIT" {
+                            let _ = write.write_all(b"221 bye\r\n").await;
+                            break;
+                        } else {
+                            b"250 localhost\r\n"
+                        };
+                        if write.write_all(reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        (port, received)
+    }
+
+    async fn send_error(client: &AsyncSmtpTransport<Tokio1Executor>, to: &str) -> smtp::Error {
+        let envelope = Envelope::new(
+            Some("reports@example.com".parse().unwrap()),
+            vec![to.parse().unwrap()],
+        )
+        .unwrap();
+        client
+            .send_raw(&envelope, b"Subject: weekly\r\n\r\nreport\r\n")
+            .await
+            .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn rejections_are_not_retried_but_deferrals_are() {
+        let (client, _) = smtp_server().await;
+
+        let rejected = send_error(&client, "rejected@example.com").await;
+        assert!(rejected.is_permanent());
+        assert!(!is_retryable(&rejected));
+
+        let deferred = send_error(&client, "busy@example.com").await;
+        assert!(deferred.is_transient());
+        assert!(is_retryable(&deferred));
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (client, _) = smtp_server().await;
+        // The server does not announce SMTPUTF8
+        let utf8 = send_error(&client, "jürgen@example.com").await;
+        assert!(utf8.is_client());
+        assert!(!is_retryable(&utf8));
+    }
+
+    #[tokio::test]
+    async fn missing_starttls_is_not_retried() {
+        // The server does not offer STARTTLS
+        let (port, _) = serve().await;
+        let tls = TlsParameters::new("localhost".to_string()).unwrap();
+        let client = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+            .port(port)
+            .tls(Tls::Required(tls))
+            .build();
+
+        let starttls = send_error(&client, "a@example.com").await;
+        assert!(starttls.is_client());
+        assert!(!is_retryable(&starttls));
+    }
+
+    #[tokio::test]
+    async fn dropped_connections_are_retried() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+        let dropped = send_error(&transport(port), "a@example.com").await;
+        assert!(is_retryable(&dropped));
+    }
+
+    #[tokio::test]
+    async fn deliver_reports_every_recipient() {
+        let (client, received) = smtp_server().await;
+        let envelope = Envelope::new(
+            Some("reports@example.com".parse().unwrap()),
+            vec![
+                "a@example.com".parse().unwrap(),
+                "rejected@example.com".parse().unwrap(),
+            ],
+        )
+        .unwrap();
+
+        let statuses = deliver(&client, &envelope, b"Subject: weekly\r\n\r\nreport\r\n").await;
+        assert_eq!(statuses[0].status, DeliveryStatus::Sent);
+        assert_eq!(statuses[0].attempts, 1);
+        assert_eq!(statuses[1].status, DeliveryStatus::Failed);
+        assert_eq!(statuses[1].attempts, 1);
+        assert_eq!(statuses[1].smtp_code.as_deref(), Some("550"));
+        assert_eq!(*received.lock().unwrap(), vec!["a@example.com"]);
+    }
+
+    fn email_details() -> EmailDetails {
+        serde_json::from_value(serde_json::json!({
+            "recipients": ["a@example.com", "not an address"],
+            "title": "Weekly",
+            "name": "weekly",
+            "message": "unused",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn report_emails_have_a_text_and_an_html_alternative() {
+        let config = SmtpConfig {
+            profile: crate::config::DEFAULT_SMTP_PROFILE.to_string(),
+            from_email: "reports@example.com".to_string(),
+            reply_to: String::new(),
+        };
+        let mut vars = TemplateVars::default();
+        vars.insert("message", "<b>Q3</b> & co");
+        vars.insert("dashboard_url", "https://o2.example.com/d?a=1&b=2");
+
+        let email = build_email(
+            b"%PDF",
+            ReportType::PDF,
+            EmailAttachmentType::Standard,
+            &email_details(),
+            &config,
+            None,
+            vars,
+        )
+        .unwrap();
+        assert_eq!(email.rejected.len(), 1);
+        assert_eq!(email.rejected[0].recipient, "not an address");
+
+        let preview = email.preview.unwrap();
+        assert!(preview
+            .html_body
+            .starts_with("<p>&lt;b&gt;Q3&lt;/b&gt; &amp; co</p>"));
+        assert!(preview
+            .html_body
+            .contains("href='https://o2.example.com/d?a=1&amp;b=2'"));
+        assert!(preview.text_body.starts_with("<b>Q3</b> & co\n"));
+        assert!(preview
+            .text_body
+            .contains("https://o2.example.com/d?a=1&b=2"));
+
+        let formatted = String::from_utf8(email.message.unwrap().formatted()).unwrap();
+        let mixed = formatted.find("multipart/mixed").unwrap();
+        let alternative = formatted.find("multipart/alternative").unwrap();
+        let text = formatted.find("Content-Type: text/plain").unwrap();
+        let html = formatted.find("Content-Type: text/html").unwrap();
+        let pdf = formatted.find("Content-Type: application/pdf").unwrap();
+        assert!(mixed < alternative && alternative < text && text < html && html < pdf);
+        assert!(formatted.contains("filename=\"Weekly.pdf\""));
+    }
+
+    #[test]
+    fn dkim_domain_matches_the_from_address() {
+        assert!(is_dkim_domain("reports@example.com", "example.com"));
+        assert!(is_dkim_domain(
+            "Reports <reports@Example.COM>",
+            "example.com"
+        ));
+        assert!(!is_dkim_domain("reports@other.com", "example.com"));
+        assert!(!is_dkim_domain("reports@mail.example.com", "example.com"));
+        assert!(!is_dkim_domain("not an address", "example.com"));
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::email::{DeliveryStatus, RecipientStatus};
+use actix_web::http::StatusCode;
+
+/// Known failures of a report run. They are returned inside [`anyhow::Error`] and are found
+/// again with [`ReportError::find`]. Each case has a stable custom code >= 1000.
+#[derive(Debug)]
+pub enum ReportError {
+    /// The report payload is invalid, e.g. a dashboard without tabs
+    InvalidReport(String),
+    InvalidTimerange(String),
+    BrowserLaunchFailed(String),
+    /// The O2 login page could not be loaded or the report user could not log in
+    LoginFailed(String),
+    OrgNavigationFailed(String),
+    DashboardNotFound(String),
+    PanelLoadTimeout(String),
+    /// The PDF, screenshot or CSV of the dashboard could not be captured
+    CaptureFailed(String),
+    /// The SMTP server rejected all recipients
+    SmtpRejectedRecipient(String),
+    /// The SMTP server could not be reached or did not accept the connection
+    SmtpUnavailable(String),
+    /// The org used up one of its limits, see [`crate::limits`]
+    LimitExceeded(String),
+}
+
+impl ReportError {
+    pub fn code(&self) -> u16 {
+        match self {
+            ReportError::InvalidReport(_) => 1001,
+            ReportError::InvalidTimerange(_) => 1002,
+            ReportError::BrowserLaunchFailed(_) => 1003,
+            ReportError::LoginFailed(_) => 1004,
+            ReportError::OrgNavigationFailed(_) => 1005,
+            ReportError::DashboardNotFound(_) => 1006,
+            ReportError::PanelLoadTimeout(_) => 1007,
+            ReportError::CaptureFailed(_) => 1008,
+            ReportError::SmtpRejectedRecipient(_) => 1009,
+            ReportError::SmtpUnavailable(_) => 1010,
+            ReportError::LimitExceeded(_) => 1011,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ReportError::InvalidReport(_) | ReportError::InvalidTimerange(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            ReportError::BrowserLaunchFailed(_) | ReportError::CaptureFailed(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            ReportError::LoginFailed(_) | ReportError::OrgNavigationFailed(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            ReportError::DashboardNotFound(_) => StatusCode::NOT_FOUND,
+            ReportError::PanelLoadTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ReportError::SmtpRejectedRecipient(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ReportError::SmtpUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ReportError::LimitExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// Short description of the case, the details are in [`ReportError::detail`]
+    pub fn message(&self) -> &'static str {
+        match self {
+            ReportError::InvalidReport(_) => "Invalid report",
+            ReportError::InvalidTimerange(_) => "Invalid timerange",
+            ReportError::BrowserLaunchFailed(_) => "Browser could not be launched",
+            ReportError::LoginFailed(_) => "Login to O2 failed",
+            ReportError::OrgNavigationFailed(_) => "Navigation to the organization failed",
+            ReportError::DashboardNotFound(_) => "Dashboard not found",
+            ReportError::PanelLoadTimeout(_) => "Dashboard panels did not load in time",
+            ReportError::CaptureFailed(_) => "Report could not be captured",
+            ReportError::SmtpRejectedRecipient(_) => "SMTP server rejected the recipients",
+            ReportError::SmtpUnavailable(_) => "SMTP server unavailable",
+            ReportError::LimitExceeded(_) => "Org limit exceeded",
+        }
+    }
+
+    pub fn detail(&self) -> &str {
+        match self {
+            ReportError::InvalidReport(detail)
+            | ReportError::InvalidTimerange(detail)
+            | ReportError::BrowserLaunchFailed(detail)
+            | ReportError::LoginFailed(detail)
+            | ReportError::OrgNavigationFailed(detail)
+            | ReportError::DashboardNotFound(detail)
+            | ReportError::PanelLoadTimeout(detail)
+            | ReportError::CaptureFailed(detail)
+            | ReportError::SmtpRejectedRecipient(detail)
+            | ReportError::SmtpUnavailable(detail)
+            | ReportError::LimitExceeded(detail) => detail,
+        }
+    }
+
+    /// Error of a report email which could not be sent to any recipient. The SMTP server is
+    /// unavailable when every recipient failed with a connection or transient error.
+    pub fn from_recipients(recipients: &[RecipientStatus]) -> Self {
+        let failed: Vec<_> = recipients
+            .iter()
+            .filter(|r| r.status == DeliveryStatus::Failed)
+            .collect();
+        let detail = failed
+            .iter()
+            .map(|r| {
+                format!(
+                    "{}: {}",
+                    r.recipient,
+                    r.error.as_deref().unwrap_or("delivery failed")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let unavailable = !failed.is_empty()
+            && failed.iter().all(|r| {
+                r.attempts > 0 && !r.smtp_code.as_deref().is_some_and(|c| c.starts_with('5'))
+            });
+        if unavailable {
+            ReportError::SmtpUnavailable(detail)
+        } else {
+            ReportError::SmtpRejectedRecipient(detail)
+        }
+    }
+
+    /// The report error in the chain of the error, if any
+    pub fn find(e: &anyhow::Error) -> Option<&ReportError> {
+        e.chain()
+            .find_map(|cause| cause.downcast_ref::<ReportError>())
+    }
+}
+
+impl std::fmt::Display for ReportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.message(), self.detail())
+    }
+}
+
+impl std::error::Error for ReportError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipient(
+        recipient: &str,
+        status: DeliveryStatus,
+        attempts: u32,
+        smtp_code: Option<&str>,
+    ) -> RecipientStatus {
+        RecipientStatus {
+            recipient: recipient.to_string(),
+            status,
+            attempts,
+            smtp_code: smtp_code.map(str::to_string),
+            error: (status != DeliveryStatus::Sent).then(|| format!("{recipient} failed")),
+        }
+    }
+
+    #[test]
+    fn cases_map_to_codes_and_statuses() {
+        let detail = String::new;
+        for (error, code, status) in [
+            (ReportError::InvalidReport(detail()), 1001, 400),
+            (ReportError::InvalidTimerange(detail()), 1002, 400),
+            (ReportError::BrowserLaunchFailed(detail()), 1003, 500),
+            (ReportError::LoginFailed(detail()), 1004, 502),
+            (ReportError::OrgNavigationFailed(detail()), 1005, 502),
+            (ReportError::DashboardNotFound(detail()), 1006, 404),
+            (ReportError::PanelLoadTimeout(detail()), 1007, 504),
+            (ReportError::CaptureFailed(detail()), 1008, 500),
+            (ReportError::SmtpRejectedRecipient(detail()), 1009, 422),
+            (ReportError::SmtpUnavailable(detail()), 1010, 503),
+            (ReportError::LimitExceeded(detail()), 1011, 429),
+        ] {
+            assert_eq!(error.code(), code, "{error:?}");
+            assert_eq!(error.status().as_u16(), status, "{error:?}");
+        }
+    }
+
+    #[test]
+    fn report_errors_are_found_in_the_chain() {
+        let error = anyhow::Error::from(ReportError::PanelLoadTimeout("panel 3".to_string()))
+            .context("Error rendering report default/weekly");
+        let found = ReportError::find(&error).unwrap();
+        assert_eq!(found.code(), 1007);
+        assert_eq!(
+            found.to_string(),
+            "Dashboard panels did not load in time: panel 3"
+        );
+        assert!(ReportError::find(&anyhow::anyhow!("other error")).is_none());
+    }
+
+    #[test]
+    fn transient_failures_of_all_recipients_are_unavailable() {
+        let error = ReportError::from_recipients(&[
+            recipient("a@example.com", DeliveryStatus::Failed, 3, Some("421")),
+            recipient("b@example.com", DeliveryStatus::Failed, 1, None),
+        ]);
+        assert!(matches!(error, ReportError::SmtpUnavailable(_)));
+        assert_eq!(
+            error.detail(),
+            "a@example.com: a@example.com failed, b@example.com: b@example.com failed"
+        );
+    }
+
+    #[test]
+    fn permanent_failures_are_rejections() {
+        let error = ReportError::from_recipients(&[
+            recipient("a@example.com", DeliveryStatus::Failed, 3, Some("421")),
+            recipient("b@example.com", DeliveryStatus::Failed, 1, Some("550")),
+        ]);
+        assert!(matches!(error, ReportError::SmtpRejectedRecipient(_)));
+
+        // Invalid addresses are never sent to the SMTP server
+        let error = ReportError::from_recipients(&[recipient(
+            "not an address",
+            DeliveryStatus::Failed,
+            0,
+            None,
+        )]);
+        assert!(matches!(error, ReportError::SmtpRejectedRecipient(_)));
+    }
+
+    #[test]
+    fn partly_failed_emails_only_list_the_failed_recipients() {
+        let error = ReportError::from_recipients(&[
+            recipient("a@example.com", DeliveryStatus::Sent, 1, None),
+            recipient("b@example.com", DeliveryStatus::Queued, 1, Some("451")),
+            recipient("c@example.com", DeliveryStatus::Failed, 1, Some("550")),
+        ]);
+        assert!(matches!(error, ReportError::SmtpRejectedRecipient(_)));
+        assert_eq!(error.detail(), "c@example.com: c@example.com failed");
+    }
+}
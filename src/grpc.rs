@@ -0,0 +1,427 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{
+    auth,
+    config::{CONFIG, DEFAULT_TIMEZONE},
+    email::RecipientStatus,
+    error::ReportError,
+    jobs::{self, Job, JobArtifact, JobRequest},
+    limits, render_queue,
+    router::check_report,
+    template::EmailTemplate,
+    EmailAttachmentType, EmailDetails, Report, ReportAttachmentDimensions, ReportDashboard,
+    ReportDashboardVariable, ReportTimerange, ReportTimerangeType, ReportType, SmtpConfig,
+};
+use actix_web::http::StatusCode;
+use proto::{
+    report_service_server::{ReportService, ReportServiceServer},
+    GetJobRequest, RenderReportResponse, ReportRequest,
+};
+use serde::Serialize;
+use std::{future::Future, net::SocketAddr};
+use tonic::{service::interceptor::InterceptedService, Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("report");
+}
+
+/// Name of a lowercase serde enum such as [`jobs::JobStatus`]
+fn enum_name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+impl From<RecipientStatus> for proto::RecipientStatus {
+    fn from(status: RecipientStatus) -> Self {
+        Self {
+            recipient: status.recipient,
+            status: status.status.as_str().to_string(),
+            attempts: status.attempts,
+            smtp_code: status.smtp_code,
+            error: status.error,
+        }
+    }
+}
+
+impl From<JobArtifact> for proto::JobArtifact {
+    fn from(artifact: JobArtifact) -> Self {
+        Self {
+            filename: artifact.filename,
+            content_type: artifact.content_type,
+            size: artifact.size as u64,
+        }
+    }
+}
+
+impl From<Job> for proto::Job {
+    fn from(job: Job) -> Self {
+        Self {
+            status: enum_name(&job.status),
+            id: job.id,
+            org_id: job.org_id,
+            report_name: job.report_name,
+            error: job.error,
+            error_code: job.error_code.map(u32::from),
+            created_at: job.created_at,
+            updated_at: job.updated_at,
+            artifact: job.artifact.map(Into::into),
+            recipients: job.recipients.into_iter().map(Into::into).collect(),
+            eml_path: job.eml_path,
+            trace_id: job.trace_id,
+        }
+    }
+}
+
+/// Maps the status of a rejected report to the matching grpc status
+fn to_status((status, msg): (StatusCode, String)) -> Status {
+    match status {
+        StatusCode::BAD_REQUEST => Status::invalid_argument(msg),
+        StatusCode::CONFLICT | StatusCode::UNPROCESSABLE_ENTITY => Status::failed_precondition(msg),
+        StatusCode::NOT_FOUND => Status::not_found(msg),
+        StatusCode::GATEWAY_TIMEOUT => Status::deadline_exceeded(msg),
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(msg),
+        StatusCode::TOO_MANY_REQUESTS => Status::resource_exhausted(msg),
+        _ => Status::internal(msg),
+    }
+}
+
+/// Maps a failed report run to the grpc status of its [`ReportError`]
+fn error_status(e: &anyhow::Error) -> Status {
+    match ReportError::find(e) {
+        Some(error) => to_status((error.status(), error.to_string())),
+        None => Status::internal(e.to_string()),
+    }
+}
+
+/// Invalid value of a proto enum
+fn invalid_enum(field: &str, value: i32) -> Status {
+    Status::invalid_argument(format!("Invalid report: unknown {field} {value}"))
+}
+
+impl TryFrom<proto::ReportDashboard> for ReportDashboard {
+    type Error = Status;
+
+    fn try_from(dashboard: proto::ReportDashboard) -> Result<Self, Self::Error> {
+        let report_type = match proto::ReportType::try_from(dashboard.report_type) {
+            Ok(proto::ReportType::Pdf) => ReportType::PDF,
+            Ok(proto::ReportType::Png) => ReportType::PNG,
+            Ok(proto::ReportType::Csv) => ReportType::Csv,
+            Ok(proto::ReportType::Cache) => ReportType::Cache,
+            Err(_) => return Err(invalid_enum("report_type", dashboard.report_type)),
+        };
+        let attachment_type = dashboard.email_attachment_type;
+        let email_attachment_type = match proto::EmailAttachmentType::try_from(attachment_type) {
+            Ok(proto::EmailAttachmentType::Standard) => EmailAttachmentType::Standard,
+            Ok(proto::EmailAttachmentType::Inline) => EmailAttachmentType::Inline,
+            Err(_) => return Err(invalid_enum("email_attachment_type", attachment_type)),
+        };
+        let timerange = match dashboard.timerange {
+            Some(timerange) => ReportTimerange {
+                range_type: match proto::TimerangeType::try_from(timerange.r#type) {
+                    Ok(proto::TimerangeType::Relative) => ReportTimerangeType::Relative,
+                    Ok(proto::TimerangeType::Absolute) => ReportTimerangeType::Absolute,
+                    Err(_) => return Err(invalid_enum("timerange type", timerange.r#type)),
+                },
+                period: timerange.period,
+                from: timerange.from,
+                to: timerange.to,
+            },
+            None => ReportTimerange::default(),
+        };
+        Ok(Self {
+            dashboard: dashboard.dashboard,
+            folder: dashboard.folder,
+            tabs: dashboard.tabs,
+            variables: dashboard
+                .variables
+                .into_iter()
+                .map(|v| ReportDashboardVariable {
+                    key: v.key,
+                    value: v.value,
+                    id: v.id,
+                })
+                .collect(),
+            timerange,
+            report_type,
+            email_attachment_type,
+            attachment_dimensions: dashboard
+                .attachment_dimensions
+                .map(|d| ReportAttachmentDimensions {
+                    height: d.height,
+                    width: d.width,
+                })
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl TryFrom<proto::Report> for Report {
+    type Error = Status;
+
+    fn try_from(report: proto::Report) -> Result<Self, Self::Error> {
+        let email_details = report.email_details.unwrap_or_default();
+        let template = email_details.template.unwrap_or_default();
+        Ok(Self {
+            dashboards: report
+                .dashboards
+                .into_iter()
+                .map(ReportDashboard::try_from)
+                .collect::<Result<_, _>>()?,
+            email_details: EmailDetails {
+                recipients: email_details.recipients,
+                title: email_details.title,
+                name: email_details.name,
+                message: email_details.message,
+                dashb_url: String::new(),
+                image_preview: email_details.image_preview,
+                template: EmailTemplate {
+                    subject: template.subject,
+                    html_body: template.html_body,
+                    text_body: template.text_body,
+                },
+                trusted_html: email_details.trusted_html,
+                smtp_profile: email_details.smtp_profile,
+            },
+            callback_url: report.callback_url,
+        })
+    }
+}
+
+/// Reads the report of the request
+#[allow(clippy::result_large_err)]
+fn parse_report(request: &mut ReportRequest) -> Result<Report, Status> {
+    match request.report.take() {
+        Some(report) => report.try_into(),
+        None => Err(Status::invalid_argument(
+            "Invalid report: report is missing",
+        )),
+    }
+}
+
+fn timezone(request: &ReportRequest) -> String {
+    if request.timezone.is_empty() {
+        DEFAULT_TIMEZONE.to_string()
+    } else {
+        request.timezone.clone()
+    }
+}
+
+pub struct ReportServer;
+
+#[tonic::async_trait]
+impl ReportService for ReportServer {
+    async fn send_report(
+        &self,
+        request: Request<ReportRequest>,
+    ) -> Result<Response<proto::Job>, Status> {
+        let mut request = request.into_inner();
+        let report = parse_report(&mut request)?;
+        let (org_id, report_name) = (request.org_id.clone(), request.report_name.clone());
+        let (dashboard, report_type) =
+            check_report(&report, &org_id, &report_name).map_err(to_status)?;
+        let smtp_config = if report_type == ReportType::Cache {
+            None
+        } else {
+            let config =
+                SmtpConfig::for_report(&org_id, report.email_details.smtp_profile.as_deref())
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            Some(config)
+        };
+        let ticket = render_queue::enqueue(&org_id)
+            .map_err(|e| Status::resource_exhausted(e.to_string()))?;
+        let admission = limits::admit(&org_id, report.email_details.recipients.len())
+            .map_err(|e| error_status(&ReportError::from(e).into()))?;
+
+        let job = jobs::submit(JobRequest {
+            timezone: timezone(&request),
+            org_id,
+            report_name,
+            report,
+            dashboard,
+            report_type,
+            smtp_config,
+            ticket,
+            admission,
+        });
+        Ok(Response::new(job.into()))
+    }
+
+    async fn render_report(
+        &self,
+        request: Request<ReportRequest>,
+    ) -> Result<Response<RenderReportResponse>, Status> {
+        let mut request = request.into_inner();
+        let report = parse_report(&mut request)?;
+        let (org_id, report_name) = (&request.org_id, &request.report_name);
+        let (dashboard, _) = check_report(&report, org_id, report_name).map_err(to_status)?;
+        let report_type = dashboard.report_type;
+        let (Some(content_type), Some(extension)) =
+            (report_type.content_type(), report_type.file_extension())
+        else {
+            return Err(Status::invalid_argument(
+                "Report type must be pdf, png or csv",
+            ));
+        };
+        let ticket =
+            render_queue::enqueue(org_id).map_err(|e| Status::resource_exhausted(e.to_string()))?;
+        let admission =
+            limits::admit(org_id, 0).map_err(|e| error_status(&ReportError::from(e).into()))?;
+
+        let permit = ticket.wait().await;
+        let generated = crate::generate_report(
+            &dashboard,
+            org_id,
+            report_name,
+            &CONFIG.auth.user_email,
+            &CONFIG.auth.user_password,
+            &CONFIG.common.o2_web_uri,
+            &timezone(&request),
+            report_type,
+            false,
+        )
+        .await
+        .map_err(|e| {
+            log::error!("Error rendering report {org_id}/{report_name}: {e}");
+            limits::refund_render(&admission);
+            error_status(&e)
+        })?;
+        drop(permit);
+
+        Ok(Response::new(RenderReportResponse {
+            filename: format!("{}.{extension}", crate::sanitize_filename(report_name)),
+            content_type: content_type.to_string(),
+            content: generated.attachment,
+            dashboard_url: generated.dashboard_url,
+        }))
+    }
+
+    async fn get_job(
+        &self,
+        request: Request<GetJobRequest>,
+    ) -> Result<Response<proto::Job>, Status> {
+        let id = request.into_inner().id;
+        match jobs::get(&id) {
+            Some(job) => Ok(Response::new(job.into())),
+            None => Err(Status::not_found(format!("Job {id} not found"))),
+        }
+    }
+}
+
+/// Rejects requests without the `ZO_INTERNAL_GRPC_TOKEN` in the `authorization` metadata,
+/// sent as `Bearer <token>` or as the bare token
+#[allow(clippy::result_large_err)]
+fn check_token(req: Request<()>) -> Result<Request<()>, Status> {
+    let token = req
+        .metadata()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.strip_prefix("Bearer ").unwrap_or(v).trim());
+    match token {
+        Some(token)
+            if auth::token_eq(token.as_bytes(), CONFIG.grpc.internal_grpc_token.as_bytes()) =>
+        {
+            Ok(req)
+        }
+        _ => Err(Status::unauthenticated(
+            "Missing or invalid internal grpc token",
+        )),
+    }
+}
+
+/// Serves the report service until the shutdown future completes
+pub async fn serve(
+    addr: SocketAddr,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), anyhow::Error> {
+    let max_size = CONFIG.grpc.max_message_size * 1024 * 1024;
+    let service = ReportServiceServer::new(ReportServer)
+        .max_decoding_message_size(max_size)
+        .max_encoding_message_size(max_size);
+    tonic::transport::Server::builder()
+        .add_service(InterceptedService::new(service, check_token))
+        .serve_with_shutdown(addr, shutdown)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dashboard() -> proto::ReportDashboard {
+        proto::ReportDashboard {
+            dashboard: "d1".to_string(),
+            folder: "default".to_string(),
+            tabs: vec!["t1".to_string()],
+            report_type: proto::ReportType::Png as i32,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn typed_report_is_converted() {
+        let mut request = ReportRequest {
+            report: Some(proto::Report {
+                dashboards: vec![dashboard()],
+                email_details: Some(proto::EmailDetails {
+                    recipients: vec!["a@example.com".to_string()],
+                    ..Default::default()
+                }),
+                callback_url: None,
+            }),
+            ..Default::default()
+        };
+        let report = parse_report(&mut request).unwrap();
+        let dashboard = &report.dashboards[0];
+        assert_eq!(dashboard.report_type, ReportType::PNG);
+        assert_eq!(
+            dashboard.email_attachment_type,
+            EmailAttachmentType::Standard
+        );
+        assert_eq!(dashboard.timerange.period, "1w");
+        assert_eq!(report.email_details.recipients, ["a@example.com"]);
+        assert_eq!(timezone(&request), DEFAULT_TIMEZONE);
+    }
+
+    #[test]
+    fn unknown_enum_values_are_rejected() {
+        let mut request = ReportRequest {
+            report: Some(proto::Report {
+                dashboards: vec![proto::ReportDashboard {
+                    report_type: 42,
+                    ..dashboard()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let status = parse_report(&mut request).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn missing_report_is_rejected() {
+        let mut request = ReportRequest {
+            org_id: "default".to_string(),
+            report_name: "weekly".to_string(),
+            ..Default::default()
+        };
+        let status = parse_report(&mut request).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
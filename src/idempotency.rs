@@ -0,0 +1,218 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{config::CONFIG, jobs::Job, router::HttpResponse};
+use actix_web::http::StatusCode;
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+/// Send requests by idempotency key
+static ENTRIES: Lazy<Mutex<HashMap<String, Entry>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Result of a send request, returned again for repeated requests with the same key
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Response(StatusCode, HttpResponse),
+    /// Job of an async request
+    Job(Job),
+}
+
+impl Outcome {
+    /// Server errors, timeouts and rejections by a limit may pass when the request is retried,
+    /// so they are not returned again for the key
+    fn is_retryable(&self) -> bool {
+        match self {
+            Outcome::Response(status, _) => {
+                status.is_server_error()
+                    || *status == StatusCode::REQUEST_TIMEOUT
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Outcome::Job(_) => false,
+        }
+    }
+}
+
+struct Entry {
+    id: u64,
+    /// Hash of the request, a key can't be reused for another request
+    fingerprint: u64,
+    outcome: watch::Receiver<Option<Outcome>>,
+    /// Set once the run finished
+    expires_at: Option<Instant>,
+}
+
+/// See [`claim`]
+pub enum Claim {
+    /// First request with the key, it runs the report and completes the [`Pending`]
+    New(Pending),
+    /// An earlier request with the key, see [`wait`]
+    Existing(watch::Receiver<Option<Outcome>>),
+    /// The key was used for a different request
+    Mismatch,
+}
+
+/// The run of the first request with a key. Dropping it without completing it frees the key,
+/// e.g. when the request is invalid or the render queue is full.
+pub struct Pending {
+    key: String,
+    id: u64,
+    tx: Option<watch::Sender<Option<Outcome>>>,
+}
+
+impl Pending {
+    /// Hands the outcome to the waiting requests and stores it for `ZO_IDEMPOTENCY_WINDOW_SECS`.
+    /// Retryable failures are not stored, the key is freed so a retry runs the report again.
+    pub fn complete(mut self, outcome: Outcome) {
+        let mut entries = ENTRIES.lock().unwrap();
+        if entries.get(&self.key).is_some_and(|e| e.id == self.id) {
+            if outcome.is_retryable() {
+                entries.remove(&self.key);
+            } else if let Some(entry) = entries.get_mut(&self.key) {
+                entry.expires_at =
+                    Some(Instant::now() + Duration::from_secs(CONFIG.idempotency.window_secs));
+            }
+        }
+        if let Some(tx) = self.tx.take() {
+            tx.send_replace(Some(outcome));
+        }
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if self.tx.is_none() {
+            return;
+        }
+        let mut entries = ENTRIES.lock().unwrap();
+        if entries.get(&self.key).is_some_and(|e| e.id == self.id) {
+            entries.remove(&self.key);
+        }
+    }
+}
+
+/// Looks up the key, or registers it for a new run when it is unknown or expired
+pub fn claim(key: &str, fingerprint: u64) -> Claim {
+    let now = Instant::now();
+    let mut entries = ENTRIES.lock().unwrap();
+    entries.retain(|_, e| e.expires_at.is_none_or(|t| t > now));
+    if let Some(entry) = entries.get(key) {
+        if entry.fingerprint != fingerprint {
+            return Claim::Mismatch;
+        }
+        return Claim::Existing(entry.outcome.clone());
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = watch::channel(None);
+    entries.insert(
+        key.to_string(),
+        Entry {
+            id,
+            fingerprint,
+            outcome: rx,
+            expires_at: None,
+        },
+    );
+    Claim::New(Pending {
+        key: key.to_string(),
+        id,
+        tx: Some(tx),
+    })
+}
+
+/// Waits for the run of the earlier request. Returns `None` when it stopped before running
+/// the report, the key can then be claimed again.
+pub async fn wait(mut rx: watch::Receiver<Option<Outcome>>) -> Option<Outcome> {
+    rx.wait_for(|outcome| outcome.is_some())
+        .await
+        .ok()
+        .and_then(|outcome| outcome.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: StatusCode) -> Outcome {
+        Outcome::Response(status, HttpResponse::new(String::new(), status.into()))
+    }
+
+    fn claim_new(key: &str) -> Pending {
+        match claim(key, 1) {
+            Claim::New(pending) => pending,
+            _ => panic!("key {key} is taken"),
+        }
+    }
+
+    #[tokio::test]
+    async fn successful_outcomes_are_replayed() {
+        let pending = claim_new("success");
+        let Claim::Existing(rx) = claim("success", 1) else {
+            panic!("key is not taken");
+        };
+        pending.complete(response(StatusCode::OK));
+        let Some(Outcome::Response(status, _)) = wait(rx).await else {
+            panic!("no outcome");
+        };
+        assert_eq!(status, StatusCode::OK);
+        assert!(matches!(claim("success", 1), Claim::Existing(_)));
+        assert!(matches!(claim("success", 2), Claim::Mismatch));
+    }
+
+    #[tokio::test]
+    async fn terminal_client_errors_are_replayed() {
+        claim_new("invalid").complete(response(StatusCode::UNPROCESSABLE_ENTITY));
+        assert!(matches!(claim("invalid", 1), Claim::Existing(_)));
+    }
+
+    #[tokio::test]
+    async fn retryable_failures_free_the_key() {
+        for (key, status) in [
+            ("unavailable", StatusCode::SERVICE_UNAVAILABLE),
+            ("timeout", StatusCode::GATEWAY_TIMEOUT),
+            ("limited", StatusCode::TOO_MANY_REQUESTS),
+        ] {
+            let pending = claim_new(key);
+            let Claim::Existing(rx) = claim(key, 1) else {
+                panic!("key {key} is not taken");
+            };
+            pending.complete(response(status));
+            // Requests which joined the run still get its outcome
+            assert!(wait(rx).await.is_some());
+            claim_new(key);
+        }
+    }
+
+    #[tokio::test]
+    async fn dropped_runs_free_the_key() {
+        let pending = claim_new("dropped");
+        let Claim::Existing(rx) = claim("dropped", 1) else {
+            panic!("key is not taken");
+        };
+        drop(pending);
+        assert!(wait(rx).await.is_none());
+        claim_new("dropped");
+    }
+}
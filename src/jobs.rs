@@ -0,0 +1,415 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{
+    config::CONFIG,
+    email::RecipientStatus,
+    error::ReportError,
+    limits::{self, Admission},
+    outbox,
+    render_queue::Ticket,
+    telemetry,
+    webhook::{self, RunOutcome, RunStatus},
+    Report, ReportDashboard, ReportType, SmtpConfig,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf, sync::RwLock};
+use tokio::time::Duration;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+/// Jobs by id, loaded from `ZO_JOBS_DIR` on first use when jobs are persisted
+static JOBS: Lazy<RwLock<HashMap<String, Job>>> = Lazy::new(|| RwLock::new(load()));
+
+/// Artifacts of the jobs kept in memory, only used when jobs are not persisted
+static ARTIFACTS: Lazy<RwLock<HashMap<String, Vec<u8>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Rendering,
+    Sending,
+    Done,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Done | JobStatus::Failed)
+    }
+}
+
+/// The rendered report file of a job
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct JobArtifact {
+    pub filename: String,
+    pub content_type: String,
+    pub size: usize,
+}
+
+/// A report run submitted with `?async=true`
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Job {
+    pub id: String,
+    pub org_id: String,
+    pub report_name: String,
+    pub status: JobStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Custom code of the error, see [`ReportError::code`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<u16>,
+    /// Creation time in microseconds
+    pub created_at: i64,
+    /// Last update time in microseconds
+    pub updated_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<JobArtifact>,
+    /// Delivery result per recipient, set once the email was sent
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<RecipientStatus>,
+    /// Path of the written `.eml` file, set by the file mail transport
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eml_path: Option<String>,
+    /// Trace of the request which submitted the job
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+}
+
+/// A validated report run
+pub struct JobRequest {
+    pub org_id: String,
+    pub report_name: String,
+    pub report: Report,
+    pub dashboard: ReportDashboard,
+    pub report_type: ReportType,
+    pub timezone: String,
+    /// `None` for reports which only cache the dashboard data
+    pub smtp_config: Option<SmtpConfig>,
+    /// Place of the job in the render queue
+    pub ticket: Ticket,
+    /// The job counted against the limits of the org
+    pub admission: Admission,
+}
+
+fn job_path(id: &str) -> PathBuf {
+    PathBuf::from(&CONFIG.jobs.dir).join(format!("{id}.json"))
+}
+
+fn artifact_path(id: &str) -> PathBuf {
+    PathBuf::from(&CONFIG.jobs.dir).join(format!("{id}.artifact"))
+}
+
+/// Loads the persisted jobs. Jobs which were running when the process stopped are failed.
+fn load() -> HashMap<String, Job> {
+    let mut jobs = HashMap::new();
+    if !CONFIG.jobs.persist {
+        return jobs;
+    }
+    let dir = match std::fs::read_dir(&CONFIG.jobs.dir) {
+        Ok(dir) => dir,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::error!("Error reading jobs dir {}: {e}", CONFIG.jobs.dir);
+            }
+            return jobs;
+        }
+    };
+    for file in dir.flatten() {
+        let path = file.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let job = std::fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(serde_json::from_slice::<Job>(&data)?));
+        match job {
+            Ok(mut job) => {
+                if !job.status.is_finished() {
+                    job.status = JobStatus::Failed;
+                    job.error = Some("Interrupted by a restart of the report server".to_string());
+                    let tmp_path = path.with_extension("json.tmp");
+                    let written = serde_json::to_vec_pretty(&job)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|data| Ok(std::fs::write(&tmp_path, data)?))
+                        .and_then(|_| Ok(std::fs::rename(&tmp_path, &path)?));
+                    if let Err(e) = written {
+                        log::error!("Error persisting job {}: {e}", job.id);
+                    }
+                }
+                jobs.insert(job.id.clone(), job);
+            }
+            Err(e) => log::error!("Error reading job {}: {e}", path.display()),
+        }
+    }
+    log::info!("loaded {} report jobs from {}", jobs.len(), CONFIG.jobs.dir);
+    jobs
+}
+
+async fn persist(job: &Job) {
+    if !CONFIG.jobs.persist {
+        return;
+    }
+    let result = async {
+        tokio::fs::create_dir_all(&CONFIG.jobs.dir).await?;
+        outbox::write_atomic(job_path(&job.id), &serde_json::to_vec_pretty(job)?).await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    if let Err(e) = result.await {
+        log::error!("Error persisting job {}: {e}", job.id);
+    }
+}
+
+/// Applies the change to the job and persists it. The job is written after the lock is
+/// released, so a slow disk does not block the other jobs.
+async fn update(id: &str, f: impl FnOnce(&mut Job)) {
+    let job = {
+        let mut jobs = JOBS.write().unwrap();
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        f(job);
+        job.updated_at = chrono::Utc::now().timestamp_micros();
+        job.clone()
+    };
+    persist(&job).await;
+}
+
+/// Loads the persisted jobs, so jobs interrupted by a restart are marked as failed on startup
+pub fn init() {
+    Lazy::force(&JOBS);
+}
+
+pub fn get(id: &str) -> Option<Job> {
+    JOBS.read().unwrap().get(id).cloned()
+}
+
+/// Returns the artifact of the job with its file bytes
+pub async fn artifact(id: &str) -> Result<Option<(JobArtifact, Vec<u8>)>, anyhow::Error> {
+    let Some(artifact) = get(id).and_then(|job| job.artifact) else {
+        return Ok(None);
+    };
+    let data = if CONFIG.jobs.persist {
+        tokio::fs::read(artifact_path(id)).await?
+    } else {
+        match ARTIFACTS.read().unwrap().get(id) {
+            Some(data) => data.clone(),
+            None => return Ok(None),
+        }
+    };
+    Ok(Some((artifact, data)))
+}
+
+/// Registers the job and runs it in the background
+pub fn submit(request: JobRequest) -> Job {
+    let now = chrono::Utc::now().timestamp_micros();
+    let job = Job {
+        id: uuid::Uuid::now_v7().to_string(),
+        org_id: request.org_id.clone(),
+        report_name: request.report_name.clone(),
+        status: JobStatus::Queued,
+        error: None,
+        error_code: None,
+        created_at: now,
+        updated_at: now,
+        artifact: None,
+        recipients: vec![],
+        eml_path: None,
+        trace_id: telemetry::current_trace_id(),
+    };
+    JOBS.write().unwrap().insert(job.id.clone(), job.clone());
+    log::info!(
+        "[{}/{}] report job {} queued",
+        job.org_id,
+        job.report_name,
+        job.id
+    );
+
+    let id = job.id.clone();
+    let callback_url = request.report.callback_url.clone();
+    let mut run_status = RunStatus::new(&job.org_id, &job.report_name);
+    run_status.job_id = Some(id.clone());
+    let span = tracing::info_span!(
+        "report_job",
+        job_id = %id,
+        org_id = %job.org_id,
+        report_name = %job.report_name
+    );
+    let queued = job.clone();
+    tokio::task::spawn(
+        async move {
+            persist(&queued).await;
+            if let Err(e) = run(&id, request, &mut run_status).await {
+                log::error!("Report job {id} failed: {e}");
+                update(&id, |job| {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                    job.error_code = ReportError::find(&e).map(ReportError::code);
+                })
+                .await;
+                run_status.fail(&e);
+            }
+            webhook::notify(callback_url, run_status);
+        }
+        .instrument(span),
+    );
+    job
+}
+
+async fn run(
+    id: &str,
+    request: JobRequest,
+    run_status: &mut RunStatus,
+) -> Result<(), anyhow::Error> {
+    let JobRequest {
+        org_id,
+        report_name,
+        report,
+        dashboard,
+        report_type,
+        timezone,
+        smtp_config,
+        ticket,
+        admission,
+    } = request;
+    let permit = ticket.wait().await;
+    update(id, |job| job.status = JobStatus::Rendering).await;
+
+    let Some(smtp_config) = smtp_config else {
+        let started = std::time::Instant::now();
+        let generated = crate::generate_report(
+            &dashboard,
+            &org_id,
+            &report_name,
+            &CONFIG.auth.user_email,
+            &CONFIG.auth.user_password,
+            &CONFIG.common.o2_web_uri,
+            &timezone,
+            report_type,
+            report.email_details.image_preview,
+        )
+        .await
+        .inspect_err(|_| limits::refund_render(&admission))?;
+        run_status.render_duration_ms = Some(started.elapsed().as_millis() as u64);
+        run_status.dashboard_url = Some(generated.dashboard_url);
+        run_status.succeed();
+        update(id, |job| job.status = JobStatus::Done).await;
+        return Ok(());
+    };
+
+    let rendered = crate::render_report(
+        &report,
+        &dashboard,
+        report_type,
+        &org_id,
+        &report_name,
+        &timezone,
+        &smtp_config,
+    )
+    .await
+    .inspect_err(|_| limits::refund_render(&admission))?;
+    drop(permit);
+    run_status.artifact_size = Some(rendered.attachment.len());
+    run_status.render_duration_ms = Some(rendered.render_duration.as_millis() as u64);
+    run_status.dashboard_url = Some(rendered.dashboard_url);
+    limits::add_attachment(&org_id, rendered.attachment.len())
+        .inspect_err(|_| limits::refund_render(&admission))
+        .map_err(ReportError::from)?;
+    let artifact = JobArtifact {
+        filename: format!(
+            "{}.{}",
+            crate::sanitize_filename(&report_name),
+            report_type.file_extension().unwrap_or("bin")
+        ),
+        content_type: report_type
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string(),
+        size: rendered.attachment.len(),
+    };
+    if CONFIG.jobs.persist {
+        tokio::fs::create_dir_all(&CONFIG.jobs.dir).await?;
+        outbox::write_atomic(artifact_path(id), &rendered.attachment).await?;
+    } else {
+        ARTIFACTS
+            .write()
+            .unwrap()
+            .insert(id.to_string(), rendered.attachment);
+    }
+    update(id, |job| {
+        job.status = JobStatus::Sending;
+        job.artifact = Some(artifact);
+    })
+    .await;
+
+    let sent_email = crate::send_email(&org_id, &report_name, rendered.email, &smtp_config).await?;
+    run_status.sent(&sent_email.recipients);
+    update(id, |job| {
+        job.status = match run_status.status {
+            RunOutcome::Success => JobStatus::Done,
+            RunOutcome::Failure => JobStatus::Failed,
+        };
+        job.error = run_status.error.clone();
+        job.error_code = run_status.error_code;
+        job.recipients = sent_email.recipients;
+        job.eml_path = sent_email.eml_path;
+    })
+    .await;
+    log::info!("[{org_id}/{report_name}] report job {id} finished");
+    Ok(())
+}
+
+/// Removes the expired jobs every minute, so their artifacts don't stay in memory when no
+/// new jobs are submitted
+pub async fn run_cleanup() {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        remove_expired().await;
+    }
+}
+
+/// Removes the finished jobs older than `ZO_JOBS_RETENTION_HOURS`, their files are removed
+/// after the lock is released
+async fn remove_expired() {
+    let retention = Duration::from_secs(CONFIG.jobs.retention_hours * 3600).as_micros() as i64;
+    let now = chrono::Utc::now().timestamp_micros();
+    let mut expired = vec![];
+    JOBS.write().unwrap().retain(|id, job| {
+        if !job.status.is_finished() || now - job.updated_at <= retention {
+            return true;
+        }
+        expired.push(id.clone());
+        false
+    });
+    if expired.is_empty() {
+        return;
+    }
+    {
+        let mut artifacts = ARTIFACTS.write().unwrap();
+        for id in expired.iter() {
+            artifacts.remove(id);
+        }
+    }
+    if CONFIG.jobs.persist {
+        for id in expired.iter() {
+            let _ = tokio::fs::remove_file(job_path(id)).await;
+            let _ = tokio::fs::remove_file(artifact_path(id)).await;
+        }
+    }
+}
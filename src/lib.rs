@@ -13,25 +13,52 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+pub mod auth;
 pub mod cli;
 pub mod config;
+pub mod email;
+pub mod error;
+pub mod grpc;
+pub mod idempotency;
+pub mod jobs;
+pub mod limits;
+pub mod metrics;
+pub mod oauth2;
+pub mod openapi;
+pub mod outbox;
+pub mod readiness;
+pub mod render_queue;
 pub mod router;
+pub mod scheduler;
+pub mod telemetry;
+pub mod template;
+pub mod validation;
+pub mod webhook;
 
+use base64::prelude::{Engine, BASE64_STANDARD};
 use chromiumoxide::{
     browser::Browser,
     cdp::browser_protocol::page::{CaptureScreenshotParamsBuilder, PrintToPdfParams},
     Page,
 };
-use config::{get_chrome_launch_options, CONFIG};
+use config::{
+    check_o2_url, get_chrome_launch_options, o2_link_url, CONFIG, DEFAULT_SMTP_PROFILE,
+    SMTP_PROFILES,
+};
+use email::{DeliveryStatus, MailTransport, RecipientStatus};
+use error::ReportError;
 use futures::StreamExt;
 use lettre::{
-    message::{header::ContentType, MultiPart, SinglePart},
-    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{header::ContentType, MultiPart},
+    AsyncSmtpTransport, Message, Tokio1Executor,
 };
+use metrics::{BrowserGuard, Phase};
 use serde::{Deserialize, Serialize};
+use template::{EmailTemplate, TemplateVars};
 use tokio::time::{sleep, Duration};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum ReportType {
     #[serde(alias = "PDF")]
@@ -44,17 +71,50 @@ pub enum ReportType {
     Csv,
 }
 
+impl ReportType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportType::PDF => "pdf",
+            ReportType::Cache => "cache",
+            ReportType::PNG => "png",
+            ReportType::Csv => "csv",
+        }
+    }
+
+    /// Mime type of the rendered report, `None` for cached reports
+    pub fn content_type(&self) -> Option<&'static str> {
+        match self {
+            ReportType::PDF => Some("application/pdf"),
+            ReportType::PNG => Some("image/png"),
+            ReportType::Csv => Some("application/zip"),
+            ReportType::Cache => None,
+        }
+    }
+
+    /// File extension of the rendered report, `None` for cached reports
+    pub fn file_extension(&self) -> Option<&'static str> {
+        match self {
+            ReportType::PDF => Some("pdf"),
+            ReportType::PNG => Some("png"),
+            ReportType::Csv => Some("zip"),
+            ReportType::Cache => None,
+        }
+    }
+}
+
 fn default_report_type() -> ReportType {
     ReportType::PDF
 }
 
-#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum EmailAttachmentType {
+    /// Sends the email as a traditional attachment the user can download and (pre)view
     #[serde(alias = "Standard")]
-    Standard, // Sends the email as a traditional attachment the user can download and (pre)view
+    Standard,
+    /// Sends the attachment inline in the email body
     #[serde(alias = "Inline")]
-    Inline, // Sends the attachment inline in the email body
+    Inline,
 }
 
 fn default_attachment_type() -> EmailAttachmentType {
@@ -65,7 +125,7 @@ fn default_attachment_type() -> EmailAttachmentType {
 /// Some reports are better in wide aspect ratios, and some reports like 2 column tables are
 /// better in tall aspect ratios.
 /// If no dimensions are provided, the default will be used from chrome config env vars.
-#[derive(Serialize, Debug, Deserialize, Clone)]
+#[derive(Serialize, Debug, Deserialize, Clone, ToSchema)]
 pub struct ReportAttachmentDimensions {
     pub height: u32,
     pub width: u32,
@@ -82,30 +142,74 @@ impl Default for ReportAttachmentDimensions {
 
 #[derive(Debug, Clone)]
 pub struct SmtpConfig {
+    /// Name of the SMTP profile
+    pub profile: String,
     pub from_email: String,
     pub reply_to: String,
-    pub client: &'static AsyncSmtpTransport<Tokio1Executor>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl SmtpConfig {
+    /// Resolves the SMTP profile of a report: the profile chosen in the report, else the
+    /// profile mapped to the org, else the default profile
+    pub fn for_report(org_id: &str, profile: Option<&str>) -> Result<Self, anyhow::Error> {
+        let name = profile
+            .or_else(|| SMTP_PROFILES.orgs.get(org_id).map(|p| p.as_str()))
+            .unwrap_or(DEFAULT_SMTP_PROFILE);
+        Self::from_profile(name)
+    }
+
+    pub fn from_profile(name: &str) -> Result<Self, anyhow::Error> {
+        match SMTP_PROFILES.profiles.get(name) {
+            Some(profile) => Ok(Self {
+                profile: name.to_string(),
+                from_email: profile.from_email.clone(),
+                reply_to: profile.reply_to.clone(),
+            }),
+            None => Err(anyhow::anyhow!("Unknown SMTP profile {name}")),
+        }
+    }
+
+    /// Pooled SMTP transport of the profile
+    pub async fn client(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>, anyhow::Error> {
+        SMTP_PROFILES.transport(&self.profile).await
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct EmailDetails {
+    /// Also accepted as `recepients`. Reports without recipients only cache the dashboard data
     #[serde(alias = "recepients")]
     pub recipients: Vec<String>,
     pub title: String,
     pub name: String,
     pub message: String,
+    /// Not used by the report server, the dashboard is rendered from `ZO_O2_APP_URL` and linked
+    /// from `ZO_O2_PUBLIC_URL`
+    #[serde(default)]
     pub dashb_url: String,
     #[serde(default)]
     pub image_preview: bool,
+    /// Overrides the server-wide email template for this report
+    #[serde(default)]
+    pub template: EmailTemplate,
+    /// `message` is trusted html and is not escaped in the html body
+    #[serde(default)]
+    pub trusted_html: bool,
+    /// SMTP profile to send the report with, defaults to the profile of the org
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smtp_profile: Option<String>,
 }
 
-#[derive(Serialize, Debug, Deserialize, Clone)]
+#[derive(Serialize, Debug, Deserialize, Clone, ToSchema)]
 pub struct Report {
     pub dashboards: Vec<ReportDashboard>,
     pub email_details: EmailDetails,
+    /// Url the run status is posted to when the run finishes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
 }
 
-#[derive(Serialize, Debug, Deserialize, Clone)]
+#[derive(Serialize, Debug, Deserialize, Clone, ToSchema)]
 pub struct ReportDashboard {
     pub dashboard: String,
     pub folder: String,
@@ -115,18 +219,18 @@ pub struct ReportDashboard {
     /// The timerange of dashboard data.
     #[serde(default)]
     pub timerange: ReportTimerange,
-    // defaults to PDF
+    /// Defaults to pdf
     #[serde(default = "default_report_type")]
     pub report_type: ReportType,
-    // defaults to standard attachment
+    /// Defaults to a standard attachment
     #[serde(default = "default_attachment_type")]
     pub email_attachment_type: EmailAttachmentType,
-    // defaults to chrome settings values for height and width
+    /// Defaults to the chrome window size settings
     #[serde(default)]
     pub attachment_dimensions: ReportAttachmentDimensions,
 }
 
-#[derive(Serialize, Debug, Default, Deserialize, Clone)]
+#[derive(Serialize, Debug, Default, Deserialize, Clone, ToSchema)]
 pub struct ReportDashboardVariable {
     pub key: String,
     pub value: String,
@@ -134,7 +238,7 @@ pub struct ReportDashboardVariable {
     pub id: Option<String>,
 }
 
-#[derive(Serialize, Debug, Default, Deserialize, Clone)]
+#[derive(Serialize, Debug, Default, Deserialize, Clone, ToSchema)]
 pub enum ReportTimerangeType {
     #[default]
     #[serde(rename = "relative")]
@@ -143,13 +247,63 @@ pub enum ReportTimerangeType {
     Absolute,
 }
 
-#[derive(Serialize, Debug, Deserialize, Clone)]
+#[derive(Serialize, Debug, Deserialize, Clone, ToSchema)]
 pub struct ReportTimerange {
     #[serde(rename = "type")]
     pub range_type: ReportTimerangeType,
-    pub period: String, // 15m, 4M etc. For relative.
-    pub from: i64,      // For absolute, in microseconds
-    pub to: i64,        // For absolute, in microseconds
+    /// For relative timeranges, e.g. `15m` or `4M`, the unit is one of s, m, h, d, w or M
+    pub period: String,
+    /// For absolute timeranges, in microseconds
+    pub from: i64,
+    /// For absolute timeranges, in microseconds
+    pub to: i64,
+}
+
+impl ReportTimerange {
+    /// Resolves the timerange into `(from, to)` in microseconds.
+    /// Relative timeranges end now.
+    pub fn resolve(&self) -> Result<(i64, i64), ReportError> {
+        if let ReportTimerangeType::Absolute = self.range_type {
+            if self.from > self.to {
+                return Err(ReportError::InvalidTimerange(format!(
+                    "from {} is after to {}",
+                    self.from, self.to
+                )));
+            }
+            return Ok((self.from, self.to));
+        }
+        let period = &self.period;
+        let invalid_period = || ReportError::InvalidTimerange(format!("invalid period {period:?}"));
+        let Some(time_unit) = period.chars().last() else {
+            return Err(invalid_period());
+        };
+        let time_duration: i64 = period[..period.len() - time_unit.len_utf8()]
+            .parse()
+            .map_err(|_| invalid_period())?;
+        if time_duration <= 0 {
+            return Err(invalid_period());
+        }
+        let duration = match time_unit {
+            's' => chrono::Duration::try_seconds(time_duration),
+            'm' => chrono::Duration::try_minutes(time_duration),
+            'h' => chrono::Duration::try_hours(time_duration),
+            'd' => chrono::Duration::try_days(time_duration),
+            'w' => chrono::Duration::try_weeks(time_duration),
+            'M' => time_duration
+                .checked_mul(30)
+                .and_then(chrono::Duration::try_days),
+            _ => {
+                return Err(ReportError::InvalidTimerange(format!(
+                    "invalid period {period:?}, the unit must be s, m, h, d, w or M"
+                )))
+            }
+        };
+        let duration = duration
+            .and_then(|d| d.num_microseconds())
+            .ok_or_else(invalid_period)?;
+        let end_time = chrono::Utc::now().timestamp_micros();
+        Ok((end_time - duration, end_time))
+    }
 }
 
 impl Default for ReportTimerange {
@@ -163,6 +317,30 @@ impl Default for ReportTimerange {
     }
 }
 
+/// Output of [`generate_report`]
+#[derive(Debug, Clone)]
+pub struct GeneratedReport {
+    pub attachment: Vec<u8>,
+    /// Dashboard link for the email, pinned to the rendered timerange
+    pub dashboard_url: String,
+    pub preview_image: Option<Vec<u8>>,
+    /// Start of the rendered timerange in microseconds
+    pub from: i64,
+    /// End of the rendered timerange in microseconds
+    pub to: i64,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "generate_report",
+    skip_all,
+    fields(
+        org_id = %org_id,
+        report_name = %report_name,
+        dashboard = %dashboard.dashboard,
+        report_type = report_type.as_str()
+    )
+)]
 pub async fn generate_report(
     dashboard: &ReportDashboard,
     org_id: &str,
@@ -173,9 +351,49 @@ pub async fn generate_report(
     timezone: &str,
     report_type: ReportType,
     image_preview: bool,
-) -> Result<(Vec<u8>, String, Option<Vec<u8>>), anyhow::Error> {
+) -> Result<GeneratedReport, anyhow::Error> {
+    let result = render_dashboard(
+        dashboard,
+        org_id,
+        report_name,
+        user_id,
+        user_pass,
+        web_url,
+        timezone,
+        report_type,
+        image_preview,
+    )
+    .await;
+    let outcome = if result.is_ok() { "success" } else { "failure" };
+    metrics::REPORTS
+        .with_label_values(&[org_id, report_type.as_str(), outcome])
+        .inc();
+    if let Ok(generated) = &result {
+        if report_type != ReportType::Cache {
+            metrics::ATTACHMENT_SIZE
+                .with_label_values(&[report_type.as_str()])
+                .observe(generated.attachment.len() as f64);
+        }
+    }
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn render_dashboard(
+    dashboard: &ReportDashboard,
+    org_id: &str,
+    report_name: &str,
+    user_id: &str,
+    user_pass: &str,
+    web_url: &str,
+    timezone: &str,
+    report_type: ReportType,
+    image_preview: bool,
+) -> Result<GeneratedReport, anyhow::Error> {
     let dashboard_id = &dashboard.dashboard;
     let folder_id = &dashboard.folder;
+    let web_url = web_url.trim_end_matches('/');
+    check_o2_url(web_url)?;
 
     let user_tmp_dir = tempfile::tempdir()
         .map_err(|e| anyhow::anyhow!("Error creating temporary directory: {e}"))?;
@@ -186,19 +404,28 @@ pub async fn generate_report(
     }
 
     if dashboard.tabs.is_empty() {
-        return Err(anyhow::anyhow!("Atleast one tab is required"));
+        return Err(ReportError::InvalidReport("Atleast one tab is required".to_string()).into());
     }
     // Only one tab is supported for now
     let tab_id = &dashboard.tabs[0];
+    // Fail on an invalid timerange before launching the browser
+    dashboard.timerange.resolve()?;
 
     log::info!("[{report_name}] launching browser for dashboard {dashboard_id}");
+    let phase = Phase::BrowserLaunch.start();
     let browser_config = get_chrome_launch_options(dashboard.attachment_dimensions.clone())
         .await
         .clone()
         .user_data_dir(user_tmp_dir.path())
         .build()
-        .map_err(|e| anyhow::anyhow!("Error building browser config: {e}"))?;
-    let (mut browser, mut handler) = Browser::launch(browser_config).await?;
+        .map_err(|e| {
+            ReportError::BrowserLaunchFailed(format!("Error building browser config: {e}"))
+        })?;
+    let (mut browser, mut handler) = Browser::launch(browser_config)
+        .await
+        .map_err(|e| ReportError::BrowserLaunchFailed(e.to_string()))?;
+    let _browser_guard = BrowserGuard::new();
+    phase.finish();
     log::info!("[{report_name}] browser launched");
 
     let handle = tokio::task::spawn(async move {
@@ -213,6 +440,7 @@ pub async fn generate_report(
     log::info!(
         "[{report_name}] Navigating to web url: {web_url}/login?login_as_internal_user=true"
     );
+    let phase = Phase::Login.start();
     let page = browser
         .new_page(&format!("{web_url}/login?login_as_internal_user=true"))
         .await;
@@ -221,9 +449,10 @@ pub async fn generate_report(
         browser.wait().await?;
         handle.await?;
         log::error!("[{report_name}] Error creating new page in browser for login");
-        return Err(anyhow::anyhow!(
-            "Error creating new page in browser for login"
-        ));
+        return Err(ReportError::LoginFailed(
+            "Error creating new page in browser for login".to_string(),
+        )
+        .into());
     }
     let page = page.unwrap();
     page.disable_log().await?;
@@ -248,7 +477,7 @@ pub async fn generate_report(
             browser.wait().await?;
             handle.await?;
             browser.kill().await;
-            return Err(anyhow::anyhow!("{err_msg}"));
+            return Err(ReportError::LoginFailed(err_msg).into());
         }
     }
     log::info!("[{report_name}] headless: email input filled");
@@ -270,7 +499,7 @@ pub async fn generate_report(
             browser.wait().await?;
             handle.await?;
             browser.kill().await;
-            return Err(anyhow::anyhow!("{err_msg}"));
+            return Err(ReportError::LoginFailed(err_msg).into());
         }
     }
     log::info!("[{report_name}] headless: password input filled");
@@ -292,25 +521,26 @@ pub async fn generate_report(
             browser.wait().await?;
             handle.await?;
             browser.kill().await;
-            return Err(anyhow::anyhow!("{err_msg}"));
+            return Err(ReportError::LoginFailed(err_msg).into());
         }
     }
 
     // Does not seem to work for single page client application
     page.wait_for_navigation().await?;
     sleep(Duration::from_secs(5)).await;
+    phase.finish();
 
     let timerange = &dashboard.timerange;
-    let search_type = match report_type.clone() {
+    let search_type = match report_type {
         ReportType::Cache => "ui",
         _ => "reports",
     };
 
     // dashboard link in the email should contain data of the same period as the report
-    let (dashb_url, email_dashb_url) = match timerange.range_type {
+    let link_url = o2_link_url(web_url);
+    let (dashb_url, email_dashb_url, start_time, end_time) = match timerange.range_type {
         ReportTimerangeType::Relative => {
             let period = &timerange.period;
-            let (time_duration, time_unit) = period.split_at(period.len() - 1);
             let dashb_url = format!(
                 "{web_url}/dashboards/view?org_identifier={org_id}&dashboard={dashboard_id}&folder={folder_id}&tab={tab_id}&refresh=Off&searchtype={search_type}&period={period}&timezone={timezone}&var-Dynamic+filters=%255B%255D&print=true{dashb_vars}",
             );
@@ -318,67 +548,36 @@ pub async fn generate_report(
                 "[{report_name}] dashb_url for dashboard {folder_id}/{dashboard_id}: {dashb_url}"
             );
 
-            let time_duration: i64 = time_duration.parse()?;
-            let end_time = chrono::Utc::now().timestamp_micros();
-            let start_time = match time_unit {
-                "m" => {
-                    end_time
-                        - chrono::Duration::try_minutes(time_duration)
-                            .unwrap()
-                            .num_microseconds()
-                            .unwrap()
-                }
-                "h" => {
-                    end_time
-                        - chrono::Duration::try_hours(time_duration)
-                            .unwrap()
-                            .num_microseconds()
-                            .unwrap()
-                }
-                "d" => {
-                    end_time
-                        - chrono::Duration::try_days(time_duration)
-                            .unwrap()
-                            .num_microseconds()
-                            .unwrap()
-                }
-                "w" => {
-                    end_time
-                        - chrono::Duration::try_weeks(time_duration)
-                            .unwrap()
-                            .num_microseconds()
-                            .unwrap()
-                }
-                _ => {
-                    end_time
-                        - chrono::Duration::try_days(30 * time_duration)
-                            .unwrap()
-                            .num_microseconds()
-                            .unwrap()
-                }
-            };
+            let (start_time, end_time) = timerange.resolve()?;
 
             let email_dashb_url = format!(
-                "{web_url}/dashboards/view?org_identifier={org_id}&dashboard={dashboard_id}&folder={folder_id}&tab={tab_id}&refresh=Off&from={start_time}&to={end_time}&timezone={timezone}&var-Dynamic+filters=%255B%255D&print=true{dashb_vars}",
+                "{link_url}/dashboards/view?org_identifier={org_id}&dashboard={dashboard_id}&folder={folder_id}&tab={tab_id}&refresh=Off&from={start_time}&to={end_time}&timezone={timezone}&var-Dynamic+filters=%255B%255D&print=true{dashb_vars}",
             );
-            (dashb_url, email_dashb_url)
+            (dashb_url, email_dashb_url, start_time, end_time)
         }
         ReportTimerangeType::Absolute => {
-            let url = format!(
-                "{web_url}/dashboards/view?org_identifier={org_id}&dashboard={dashboard_id}&folder={folder_id}&tab={tab_id}&refresh=Off&searchtype={search_type}&from={}&to={}&timezone={timezone}&var-Dynamic+filters=%255B%255D&print=true{dashb_vars}",
+            let path = format!(
+                "/dashboards/view?org_identifier={org_id}&dashboard={dashboard_id}&folder={folder_id}&tab={tab_id}&refresh=Off&searchtype={search_type}&from={}&to={}&timezone={timezone}&var-Dynamic+filters=%255B%255D&print=true{dashb_vars}",
                 &timerange.from, &timerange.to
             );
+            let url = format!("{web_url}{path}");
             log::debug!(
                 "[{report_name}] dashb_url for dashboard {folder_id}/{dashboard_id}: {url}"
             );
 
-            (url.clone(), url)
+            (
+                url,
+                format!("{link_url}{path}"),
+                timerange.from,
+                timerange.to,
+            )
         }
     };
 
     log::info!(
         "[{report_name}] headless: navigating to organization: {web_url}/?org_identifier={org_id}"
     );
+    let phase = Phase::Navigation.start();
     // First navigate to the correct org
     if let Err(e) = page
         .goto(&format!("{web_url}/?org_identifier={org_id}"))
@@ -399,7 +598,7 @@ pub async fn generate_report(
         if let Err(e) = user_tmp_dir.close() {
             log::error!("[{report_name}] Error closing temporary directory: {e}");
         }
-        return Err(anyhow::anyhow!("{e}"));
+        return Err(ReportError::OrgNavigationFailed(e.to_string()).into());
     }
     page.wait_for_navigation().await?;
     sleep(Duration::from_secs(2)).await;
@@ -422,17 +621,22 @@ pub async fn generate_report(
         if let Err(e) = user_tmp_dir.close() {
             log::error!("[{report_name}] Error closing temporary directory: {e}");
         }
-        return Err(anyhow::anyhow!("{e}"));
+        return Err(ReportError::DashboardNotFound(e.to_string()).into());
     }
 
     // Wait for navigation does not really wait until it is fully loaded
     page.wait_for_navigation().await?;
+    phase.finish();
 
     log::info!("[{report_name}] waiting for data to load for dashboard {dashboard_id}");
 
     // If the span element is not rendered yet, capture whatever is loaded till now
-    match wait_for_panel_data_load(&page).await {
+    let phase = Phase::PanelLoad.start();
+    let panel_load = wait_for_panel_data_load(&page).await;
+    phase.finish();
+    match panel_load {
         Err(e) => {
+            metrics::PANEL_LOADS.with_label_values(&["timeout"]).inc();
             log::error!(
                 "[{report_name}] error finding the span element for dashboard {dashboard_id}: {e}"
             );
@@ -447,12 +651,14 @@ pub async fn generate_report(
             if let Err(e) = user_tmp_dir.close() {
                 log::error!("[{report_name}] Error closing temporary directory for dashboard {dashboard_id}: {e}");
             }
-            return Err(anyhow::anyhow!(
+            return Err(ReportError::PanelLoadTimeout(format!(
                 "[{report_name}] error finding the span element for dashboard {dashboard_id}: {e}: current url: {:#?}. Some panels could not be loaded within the timeout.",
                 page_url
-            ));
+            ))
+            .into());
         }
         Ok(dur) => {
+            metrics::PANEL_LOADS.with_label_values(&["success"]).inc();
             log::info!(
                 "[{report_name}] all panel data loaded for report dashboard: {dashboard_id} in {} seconds",
                 dur.as_secs_f64()
@@ -472,10 +678,11 @@ pub async fn generate_report(
         if let Err(e) = user_tmp_dir.close() {
             log::error!("[{report_name}] Error closing temporary directory: {e}");
         }
-        return Err(anyhow::anyhow!(
+        return Err(ReportError::LoginFailed(format!(
             "[{report_name}] main html element not rendered yet for dashboard {dashboard_id}; most likely login failed: current url: {:#?} error: {e}",
             page_url
-        ));
+        ))
+        .into());
     }
     if let Err(e) = page.find_element("div.displayDiv").await {
         let page_url = page.url().await;
@@ -489,14 +696,16 @@ pub async fn generate_report(
         if let Err(e) = user_tmp_dir.close() {
             log::error!("[{report_name}] Error closing temporary directory: {e}");
         }
-        return Err(anyhow::anyhow!(
+        return Err(ReportError::DashboardNotFound(format!(
             "[{report_name}] div.displayDiv element not rendered yet for dashboard {dashboard_id}: current url: {:#?} error: {e}",
             page_url
-        ));
+        ))
+        .into());
     }
 
     // Last two elements loaded means atleast the metric components have loaded.
     // Convert the page into pdf
+    let phase = Phase::Capture.start();
     let (attachment_data, preview_image) = match report_type {
         ReportType::PDF => {
             // Helper function to convert string to Option<bool>
@@ -530,7 +739,8 @@ pub async fn generate_report(
                     ),
                     ..Default::default()
                 })
-                .await?;
+                .await
+                .map_err(|e| ReportError::CaptureFailed(format!("Error printing the pdf: {e}")))?;
 
             let preview = if image_preview {
                 match take_screenshot(&page, org_id, dashboard_id, false).await {
@@ -549,7 +759,11 @@ pub async fn generate_report(
             (pdf_data, preview)
         }
         ReportType::PNG => (
-            take_screenshot(&page, org_id, dashboard_id, false).await?,
+            take_screenshot(&page, org_id, dashboard_id, false)
+                .await
+                .map_err(|e| {
+                    ReportError::CaptureFailed(format!("Error taking the screenshot: {e}"))
+                })?,
             None,
         ),
         // No need to capture pdf when report type is cache
@@ -568,18 +782,19 @@ pub async fn generate_report(
                     })()",
                 )
                 .await
-                .map_err(|e| anyhow::anyhow!("Failed to evaluate oo_getAllPanelsCsv: {e}"))?;
+                .map_err(|e| ReportError::CaptureFailed(format!("Failed to evaluate oo_getAllPanelsCsv: {e}")))?;
 
-            let json_str: String = evaluate_result
-                .into_value()
-                .map_err(|e| anyhow::anyhow!("Failed to read panel CSV data from page: {e}"))?;
+            let json_str: String = evaluate_result.into_value().map_err(|e| {
+                ReportError::CaptureFailed(format!("Failed to read panel CSV data from page: {e}"))
+            })?;
 
             let csv_zip = build_csv_zip(&json_str)
-                .map_err(|e| anyhow::anyhow!("Failed to build CSV zip: {e}"))?;
+                .map_err(|e| ReportError::CaptureFailed(format!("Failed to build CSV zip: {e}")))?;
 
             (csv_zip, None)
         }
     };
+    phase.finish();
 
     browser.close().await?;
     browser.wait().await?;
@@ -589,49 +804,118 @@ pub async fn generate_report(
     if let Err(e) = user_tmp_dir.close() {
         log::error!("[{report_name}] Error closing temporary directory: {e}");
     }
-    Ok((attachment_data, email_dashb_url, preview_image))
+    Ok(GeneratedReport {
+        attachment: attachment_data,
+        dashboard_url: email_dashb_url,
+        preview_image,
+        from: start_time,
+        to: end_time,
+    })
+}
+
+/// A report email ready to be delivered
+#[derive(Debug, Clone)]
+pub struct ReportEmail {
+    /// `None` when none of the recipients has a valid address
+    pub message: Option<Message>,
+    /// Recipients which were rejected while building the email
+    pub rejected: Vec<RecipientStatus>,
+    /// Rendered parts of the message, `None` when there is no message
+    pub preview: Option<EmailPreview>,
+}
+
+/// The rendered parts of a report email, returned by the preview endpoint
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct EmailPreview {
+    pub subject: String,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    pub html_body: String,
+    pub text_body: String,
+    pub attachments: Vec<EmailPreviewAttachment>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct EmailPreviewAttachment {
+    pub filename: String,
+    pub content_type: String,
+    /// Inline attachments are referenced from the html body by `cid:<filename>`
+    pub inline: bool,
+    pub size: usize,
+    /// Base64 encoded content
+    pub content: String,
+}
+
+impl EmailPreviewAttachment {
+    fn new(filename: &str, content_type: &str, inline: bool, data: &[u8]) -> Self {
+        Self {
+            filename: filename.to_string(),
+            content_type: content_type.to_string(),
+            inline,
+            size: data.len(),
+            content: BASE64_STANDARD.encode(data),
+        }
+    }
 }
 
-/// Sends emails to the [`Report`] recepients
+/// Builds the email to the [`Report`] recepients
 /// Today PDFs and PNGs are supported with the option for attaching or sending inline
-async fn send_email(
+/// The subject and body are rendered from the report [`EmailTemplate`] with `vars`,
+/// the body is sent as html with a plain-text alternative
+pub fn build_email(
     attachment_data: &[u8],
     report_type: ReportType,
     email_attachment_type: EmailAttachmentType,
-    email_details: EmailDetails,
-    config: SmtpConfig,
+    email_details: &EmailDetails,
+    config: &SmtpConfig,
     preview_image: Option<Vec<u8>>,
-) -> Result<(), anyhow::Error> {
+    mut vars: TemplateVars,
+) -> Result<ReportEmail, anyhow::Error> {
+    let template = &email_details.template;
+
+    // Unparsable addresses are reported as failed, the rest still receive the report
     let mut recepients = vec![];
+    let mut results = vec![];
     for recepient in &email_details.recipients {
-        recepients.push(recepient);
+        match recepient.parse::<lettre::message::Mailbox>() {
+            Ok(mailbox) => recepients.push(mailbox),
+            Err(e) => results.push(RecipientStatus::failed(
+                recepient,
+                0,
+                format!("Invalid email address: {e}"),
+            )),
+        }
+    }
+    if recepients.is_empty() {
+        return Ok(ReportEmail {
+            message: None,
+            rejected: results,
+            preview: None,
+        });
     }
 
+    let subject = vars.render(template.subject());
     let mut email = Message::builder()
         .from(config.from_email.parse()?)
-        .subject(format!("Openobserve Report - {}", &email_details.title));
+        .subject(subject.clone());
 
+    let to = recepients.iter().map(|r| r.to_string()).collect();
     for recepient in recepients {
-        email = email.to(recepient.parse()?);
+        email = email.to(recepient);
     }
 
     if !config.reply_to.is_empty() {
         email = email.reply_to(config.reply_to.parse()?);
     }
 
-    let attachment_type = match report_type {
-        ReportType::PDF => ContentType::parse("application/pdf")?,
-        ReportType::PNG => ContentType::parse("image/png")?,
-        ReportType::Csv => ContentType::parse("application/zip")?,
-        ReportType::Cache => return Err(anyhow::anyhow!("Cached reports are not sent via email")),
-    };
-
-    let attachment_file_extension = match report_type {
-        ReportType::PDF => "pdf",
-        ReportType::PNG => "png",
-        ReportType::Csv => "zip",
-        ReportType::Cache => return Err(anyhow::anyhow!("Cached reports are not sent via email")),
+    let (Some(attachment_mime), Some(attachment_file_extension)) =
+        (report_type.content_type(), report_type.file_extension())
+    else {
+        return Err(anyhow::anyhow!("Cached reports are not sent via email"));
     };
+    let attachment_type = ContentType::parse(attachment_mime)?;
 
     // CSV reports are always sent as a standard attachment; inline zip makes no sense
     let email_attachment_type = match report_type {
@@ -648,8 +932,16 @@ async fn send_email(
     .trim()
     .replace(" ", "_");
 
-    let email_message = match email_attachment_type {
+    // The inline image (attachment or preview) and the downloadable attachment, if any
+    let mut attachments = vec![];
+    let (inline_part, attachment_part) = match email_attachment_type {
         EmailAttachmentType::Standard => {
+            attachments.push(EmailPreviewAttachment::new(
+                &attachment_name,
+                attachment_mime,
+                false,
+                attachment_data,
+            ));
             let pdf_attachment = lettre::message::Attachment::new(attachment_name.clone())
                 .body(attachment_data.to_owned(), attachment_type);
             match preview_image {
@@ -657,72 +949,218 @@ async fn send_email(
                     let preview_cid =
                         format!("{}.preview.png", sanitize_filename(&email_details.title))
                             .replace(" ", "_");
-                    let email_html = SinglePart::html(format!(
-                        "<p>{}</p><br><img src='cid:{preview_cid}' alt='Dashboard Preview'><br><p><a href='{}' target='_blank'>Link to dashboard</a></p>",
-                        email_details.message, email_details.dashb_url
-                    ));
+                    vars.insert_html(
+                        "inline_image",
+                        &format!("<br><img src='cid:{preview_cid}' alt='Dashboard Preview'><br>"),
+                    );
+                    attachments.insert(
+                        0,
+                        EmailPreviewAttachment::new(&preview_cid, "image/png", true, &png_data),
+                    );
                     let preview_attachment = lettre::message::Attachment::new_inline(preview_cid)
                         .body(png_data, ContentType::parse("image/png")?);
-                    email
-                        .multipart(
-                            MultiPart::mixed()
-                                .singlepart(email_html)
-                                .singlepart(preview_attachment)
-                                .singlepart(pdf_attachment),
-                        )
-                        .unwrap()
+                    (Some(preview_attachment), Some(pdf_attachment))
                 }
                 None => {
-                    let email_html = SinglePart::html(format!(
-                        "{}\n\n<p><a href='{}' target='_blank'>Link to dashboard</a></p>",
-                        email_details.message, email_details.dashb_url
-                    ));
-                    email
-                        .multipart(
-                            MultiPart::mixed()
-                                .singlepart(email_html)
-                                .singlepart(pdf_attachment),
-                        )
-                        .unwrap()
+                    vars.insert_html("inline_image", "");
+                    (None, Some(pdf_attachment))
                 }
             }
         }
         EmailAttachmentType::Inline => {
             // cid should work in most email servers (should work with gmail / outlook)
-            let email_html = SinglePart::html(format!(
-                "<p>{}</p>
-                <br>
-                <br>
-                <img src='cid:{}' alt='{}'>
-                <br>
-                <br>
-                <p><a href='{}' target='_blank'>Link to dashboard</a></p>
-                ",
-                email_details.message, attachment_name, attachment_name, email_details.dashb_url
+            vars.insert_html(
+                "inline_image",
+                &format!(
+                    "<br><br><img src='cid:{attachment_name}' alt='{attachment_name}'><br><br>"
+                ),
+            );
+            attachments.push(EmailPreviewAttachment::new(
+                &attachment_name,
+                attachment_mime,
+                true,
+                attachment_data,
             ));
             let attachment = lettre::message::Attachment::new_inline(attachment_name)
                 .body(attachment_data.to_owned(), attachment_type);
-            email
-                .multipart(
-                    MultiPart::mixed()
-                        .singlepart(email_html)
-                        .singlepart(attachment),
-                )
-                .unwrap()
+            (Some(attachment), None)
         }
     };
 
-    // Send the email
-    match config.client.send(email_message).await {
-        Ok(_) => {
-            log::info!(
-                "email sent successfully for the report {}",
-                &email_details.name
-            );
-            Ok(())
+    // Every email carries a plain-text alternative of the html body
+    let text_body = vars.render(template.text_body());
+    let html_body = vars.render_html(template.html_body());
+    let preview = EmailPreview {
+        subject,
+        from: config.from_email.clone(),
+        to,
+        reply_to: (!config.reply_to.is_empty()).then(|| config.reply_to.clone()),
+        html_body: html_body.clone(),
+        text_body: text_body.clone(),
+        attachments,
+    };
+    let mut body =
+        MultiPart::mixed().multipart(MultiPart::alternative_plain_html(text_body, html_body));
+    if let Some(part) = inline_part {
+        body = body.singlepart(part);
+    }
+    if let Some(part) = attachment_part {
+        body = body.singlepart(part);
+    }
+    let mut email_message = email.multipart(body)?;
+    if let Some(dkim_config) = email::dkim_config_for(&config.from_email) {
+        email_message.sign(dkim_config);
+    }
+
+    Ok(ReportEmail {
+        message: Some(email_message),
+        rejected: results,
+        preview: Some(preview),
+    })
+}
+
+/// A rendered report and its email
+#[derive(Debug)]
+pub struct RenderedReport {
+    pub attachment: Vec<u8>,
+    pub dashboard_url: String,
+    pub email: ReportEmail,
+    pub render_duration: Duration,
+}
+
+/// Renders the dashboard of the report and builds the email to its recipients
+#[tracing::instrument(
+    name = "render_report",
+    skip_all,
+    fields(org_id = %org_id, report_name = %report_name, dashboard = %dashboard.dashboard)
+)]
+pub async fn render_report(
+    report: &Report,
+    dashboard: &ReportDashboard,
+    report_type: ReportType,
+    org_id: &str,
+    report_name: &str,
+    timezone: &str,
+    smtp_config: &SmtpConfig,
+) -> Result<RenderedReport, anyhow::Error> {
+    let started = std::time::Instant::now();
+    let generated = generate_report(
+        dashboard,
+        org_id,
+        report_name,
+        &CONFIG.auth.user_email,
+        &CONFIG.auth.user_password,
+        &CONFIG.common.o2_web_uri,
+        timezone,
+        report_type,
+        report.email_details.image_preview,
+    )
+    .await
+    .map_err(|e| match ReportError::find(&e) {
+        Some(_) => e,
+        None => anyhow::anyhow!("Error generating report: {e}"),
+    })?;
+
+    let vars = TemplateVars::for_report(
+        org_id,
+        report_name,
+        dashboard,
+        &report.email_details,
+        timezone,
+        generated.from,
+        generated.to,
+        &generated.dashboard_url,
+    );
+    let email = build_email(
+        &generated.attachment,
+        report_type,
+        dashboard.email_attachment_type.clone(),
+        &report.email_details,
+        smtp_config,
+        generated.preview_image,
+        vars,
+    )
+    .map_err(|e| anyhow::anyhow!("Error building email: {e}"))?;
+    Ok(RenderedReport {
+        attachment: generated.attachment,
+        dashboard_url: generated.dashboard_url,
+        email,
+        render_duration: started.elapsed(),
+    })
+}
+
+/// Outcome of [`send_email`]
+#[derive(Debug, Default)]
+pub struct SentEmail {
+    /// Delivery result of every recipient
+    pub recipients: Vec<RecipientStatus>,
+    /// Path of the `.eml` file written by the file mail transport
+    pub eml_path: Option<String>,
+}
+
+/// Sends the report email with the configured mail transport, through the outbox when
+/// it is enabled
+#[tracing::instrument(
+    name = "send_email",
+    skip_all,
+    fields(org_id = %org_id, report_name = %report_name)
+)]
+pub async fn send_email(
+    org_id: &str,
+    report_name: &str,
+    email: ReportEmail,
+    config: &SmtpConfig,
+) -> Result<SentEmail, anyhow::Error> {
+    let mut sent_email = SentEmail {
+        recipients: email.rejected,
+        eml_path: None,
+    };
+    if let Some(message) = email.message {
+        let results = &mut sent_email.recipients;
+        match *email::MAIL_TRANSPORT {
+            MailTransport::File => {
+                let path = email::write_eml(&message.formatted()).await?;
+                log::info!("[{org_id}/{report_name}] email written to {path}");
+                results.extend(email::written_to(message.envelope()));
+                sent_email.eml_path = Some(path);
+            }
+            MailTransport::Stdout => {
+                email::print_eml(&message.formatted())?;
+                results.extend(email::written_to(message.envelope()));
+            }
+            MailTransport::Smtp if CONFIG.outbox.enabled => {
+                let phase = Phase::SmtpSend.start();
+                results.extend(outbox::send(org_id, report_name, &message, config).await?);
+                phase.finish();
+            }
+            MailTransport::Smtp => {
+                let phase = Phase::SmtpSend.start();
+                let client = config
+                    .client()
+                    .await
+                    .map_err(|e| ReportError::SmtpUnavailable(e.to_string()))?;
+                results.extend(
+                    email::deliver(&client, message.envelope(), &message.formatted()).await,
+                );
+                phase.finish();
+            }
         }
-        Err(e) => Err(anyhow::anyhow!("Error sending email: {e}")),
     }
+    for recipient in sent_email.recipients.iter() {
+        metrics::EMAILS
+            .with_label_values(&[org_id, recipient.status.as_str()])
+            .inc();
+    }
+    let sent = sent_email
+        .recipients
+        .iter()
+        .filter(|r| r.status == DeliveryStatus::Sent)
+        .count();
+    log::info!(
+        "email sent to {sent}/{} recipients for the report {org_id}/{report_name}",
+        sent_email.recipients.len()
+    );
+    Ok(sent_email)
 }
 
 async fn take_screenshot(
@@ -772,7 +1210,7 @@ pub async fn wait_for_panel_data_load(page: &Page) -> Result<Duration, anyhow::E
     }
 }
 
-fn sanitize_filename(filename: &str) -> String {
+pub fn sanitize_filename(filename: &str) -> String {
     filename
         .chars()
         .map(|c| {
@@ -0,0 +1,502 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{config::CONFIG, error::ReportError, metrics};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Usage of the limits by org since the start of the process
+static USAGE: Lazy<Mutex<HashMap<String, Usage>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Limits of single orgs from `ZO_LIMITS_FILE`, unset limits are taken from the `ZO_LIMIT_*`
+/// settings
+pub static ORG_LIMITS: Lazy<HashMap<String, OrgLimits>> = Lazy::new(|| {
+    let path = &CONFIG.limits.limits_file;
+    if path.is_empty() {
+        return HashMap::new();
+    }
+    let content = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Error reading limits file {path}: {e}"));
+    let file: LimitsFile = serde_json::from_str(&content)
+        .unwrap_or_else(|e| panic!("Error parsing limits file {path}: {e}"));
+    file.orgs
+});
+
+/// Content of `ZO_LIMITS_FILE`
+#[derive(Deserialize, Debug, Default)]
+struct LimitsFile {
+    #[serde(default)]
+    orgs: HashMap<String, OrgLimits>,
+}
+
+/// Limits of an org in `ZO_LIMITS_FILE`, 0 is unlimited
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct OrgLimits {
+    pub renders: Option<u64>,
+    pub render_window_secs: Option<u64>,
+    pub emails_per_day: Option<u64>,
+    pub recipients_per_day: Option<u64>,
+    pub attachment_bytes_per_day: Option<u64>,
+}
+
+/// Limits of an org with the server-wide defaults filled in, 0 is unlimited
+struct Limits {
+    renders: u64,
+    render_window: Duration,
+    emails_per_day: u64,
+    recipients_per_day: u64,
+    attachment_bytes_per_day: u64,
+}
+
+impl Limits {
+    fn of(org_id: &str) -> Self {
+        let org = ORG_LIMITS.get(org_id).cloned().unwrap_or_default();
+        let defaults = &CONFIG.limits;
+        Self {
+            renders: org.renders.unwrap_or(defaults.renders),
+            render_window: Duration::from_secs(
+                org.render_window_secs
+                    .unwrap_or(defaults.render_window_secs),
+            ),
+            emails_per_day: org.emails_per_day.unwrap_or(defaults.emails_per_day),
+            recipients_per_day: org
+                .recipients_per_day
+                .unwrap_or(defaults.recipients_per_day),
+            attachment_bytes_per_day: org
+                .attachment_bytes_per_day
+                .unwrap_or(defaults.attachment_bytes_per_day),
+        }
+    }
+}
+
+/// The limit which rejected a report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Renders,
+    Emails,
+    Recipients,
+    AttachmentBytes,
+}
+
+impl Limit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Limit::Renders => "renders",
+            Limit::Emails => "emails",
+            Limit::Recipients => "recipients",
+            Limit::AttachmentBytes => "attachment_bytes",
+        }
+    }
+}
+
+/// A report rejected by a limit of its org
+#[derive(Debug)]
+pub struct Rejection {
+    pub limit: Limit,
+    pub retry_after_secs: u64,
+    message: String,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, retry in {}s", self.message, self.retry_after_secs)
+    }
+}
+
+impl From<Rejection> for ReportError {
+    fn from(rejection: Rejection) -> Self {
+        ReportError::LimitExceeded(rejection.to_string())
+    }
+}
+
+/// A run counted by [`admit`], given back with [`refund_render`]
+#[derive(Debug)]
+pub struct Admission {
+    org_id: String,
+    recipients: usize,
+    /// Entry of the run in the render window
+    started: Instant,
+    /// Day the emails of the run were counted on
+    day: i64,
+}
+
+#[derive(Default)]
+struct Usage {
+    /// Start of the renders within the render window
+    renders: VecDeque<Instant>,
+    /// Day of the daily counters, in days since the epoch in UTC
+    day: i64,
+    emails: u64,
+    recipients: u64,
+    attachment_bytes: u64,
+}
+
+impl Usage {
+    /// Resets the daily counters on a new day
+    fn roll_over(&mut self, day: i64) {
+        if self.day != day {
+            self.day = day;
+            self.emails = 0;
+            self.recipients = 0;
+            self.attachment_bytes = 0;
+        }
+    }
+
+    /// Counts a run against `limits`, at `now` and `utc_secs` seconds since the epoch
+    fn admit(
+        &mut self,
+        org_id: &str,
+        limits: &Limits,
+        recipients: usize,
+        now: Instant,
+        utc_secs: i64,
+    ) -> Result<Admission, Rejection> {
+        self.roll_over(day_of(utc_secs));
+
+        let window = limits.render_window;
+        while self
+            .renders
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= window)
+        {
+            self.renders.pop_front();
+        }
+        let max_renders = limits.renders;
+        if max_renders > 0 && self.renders.len() as u64 >= max_renders {
+            let retry_after = self
+                .renders
+                .front()
+                .map(|t| window.saturating_sub(now.duration_since(*t)).as_secs() + 1)
+                .unwrap_or(1);
+            return Err(reject(
+                org_id,
+                Limit::Renders,
+                retry_after,
+                format!(
+                    "org {org_id} reached its limit of {max_renders} reports per {}s",
+                    window.as_secs()
+                ),
+            ));
+        }
+
+        if recipients > 0 {
+            let recipients = recipients as u64;
+            let max_emails = limits.emails_per_day;
+            if max_emails > 0 && self.emails >= max_emails {
+                return Err(reject(
+                    org_id,
+                    Limit::Emails,
+                    secs_until_tomorrow(utc_secs),
+                    format!("org {org_id} reached its limit of {max_emails} report emails per day"),
+                ));
+            }
+            let max_recipients = limits.recipients_per_day;
+            if max_recipients > 0 && self.recipients + recipients > max_recipients {
+                return Err(reject(
+                    org_id,
+                    Limit::Recipients,
+                    secs_until_tomorrow(utc_secs),
+                    format!(
+                        "org {org_id} has {} of its {max_recipients} recipients per day left, the report has {recipients}",
+                        max_recipients.saturating_sub(self.recipients)
+                    ),
+                ));
+            }
+            let max_bytes = limits.attachment_bytes_per_day;
+            if max_bytes > 0 && self.attachment_bytes >= max_bytes {
+                return Err(reject(
+                    org_id,
+                    Limit::AttachmentBytes,
+                    secs_until_tomorrow(utc_secs),
+                    format!(
+                        "org {org_id} reached its limit of {max_bytes} attachment bytes per day"
+                    ),
+                ));
+            }
+            self.emails += 1;
+            self.recipients += recipients;
+        }
+        self.renders.push_back(now);
+        Ok(Admission {
+            org_id: org_id.to_string(),
+            recipients,
+            started: now,
+            day: self.day,
+        })
+    }
+
+    /// Gives back the render and the emails of an admitted run
+    fn refund_render(&mut self, admission: &Admission) {
+        // The render may have left the window already
+        if let Some(i) = self.renders.iter().position(|t| *t == admission.started) {
+            self.renders.remove(i);
+        }
+        // The daily counters of a run admitted on an earlier day were reset already
+        if admission.recipients > 0 && self.day == admission.day {
+            self.emails = self.emails.saturating_sub(1);
+            self.recipients = self.recipients.saturating_sub(admission.recipients as u64);
+        }
+    }
+
+    fn add_attachment(
+        &mut self,
+        org_id: &str,
+        max_bytes: u64,
+        size: usize,
+        utc_secs: i64,
+    ) -> Result<(), Rejection> {
+        self.roll_over(day_of(utc_secs));
+        let size = size as u64;
+        if max_bytes > 0 && self.attachment_bytes + size > max_bytes {
+            return Err(reject(
+                org_id,
+                Limit::AttachmentBytes,
+                secs_until_tomorrow(utc_secs),
+                format!(
+                    "org {org_id} has {} of its {max_bytes} attachment bytes per day left, the report has {size}",
+                    max_bytes.saturating_sub(self.attachment_bytes)
+                ),
+            ));
+        }
+        self.attachment_bytes += size;
+        Ok(())
+    }
+}
+
+/// Seconds until the daily limits are reset at midnight UTC
+fn secs_until_tomorrow(utc_secs: i64) -> u64 {
+    (86400 - utc_secs.rem_euclid(86400)) as u64
+}
+
+/// Days since the epoch in UTC
+fn day_of(utc_secs: i64) -> i64 {
+    utc_secs.div_euclid(86400)
+}
+
+fn reject(org_id: &str, limit: Limit, retry_after_secs: u64, message: String) -> Rejection {
+    log::warn!("[{org_id}] report rejected: {message}");
+    metrics::LIMIT_REJECTIONS
+        .with_label_values(&[org_id, limit.as_str()])
+        .inc();
+    Rejection {
+        limit,
+        retry_after_secs,
+        message,
+    }
+}
+
+/// Admits a report run of the org with the given number of recipients, a run without
+/// recipients only renders. The run is counted against the limits of the org right away.
+pub fn admit(org_id: &str, recipients: usize) -> Result<Admission, Rejection> {
+    let limits = Limits::of(org_id);
+    let mut all_usage = USAGE.lock().unwrap();
+    let usage = all_usage.entry(org_id.to_string()).or_default();
+    usage.admit(
+        org_id,
+        &limits,
+        recipients,
+        Instant::now(),
+        chrono::Utc::now().timestamp(),
+    )
+}
+
+/// Gives back what [`admit`] counted for a run which failed before its email was sent, e.g.
+/// because the render failed or the attachment was rejected
+pub fn refund_render(admission: &Admission) {
+    if let Some(usage) = USAGE.lock().unwrap().get_mut(&admission.org_id) {
+        usage.refund_render(admission);
+    }
+}
+
+/// Counts the attachment of a rendered report before it is emailed, rejects it when it
+/// does not fit into the attachment bytes the org has left today
+pub fn add_attachment(org_id: &str, size: usize) -> Result<(), Rejection> {
+    let max_bytes = Limits::of(org_id).attachment_bytes_per_day;
+    let mut all_usage = USAGE.lock().unwrap();
+    let usage = all_usage.entry(org_id.to_string()).or_default();
+    usage.add_attachment(org_id, max_bytes, size, chrono::Utc::now().timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    const NOON: i64 = 20_000 * 86400 + 12 * 3600;
+
+    fn limits() -> Limits {
+        Limits {
+            renders: 2,
+            render_window: Duration::from_secs(60),
+            emails_per_day: 3,
+            recipients_per_day: 5,
+            attachment_bytes_per_day: 100,
+        }
+    }
+
+    #[test]
+    fn renders_are_limited_per_window() {
+        let (limits, mut usage, now) = (limits(), Usage::default(), Instant::now());
+        usage.admit("org", &limits, 0, now, NOON).unwrap();
+        usage
+            .admit("org", &limits, 0, now + Duration::from_secs(10), NOON)
+            .unwrap();
+        let rejection = usage
+            .admit("org", &limits, 0, now + Duration::from_secs(20), NOON)
+            .unwrap_err();
+        assert_eq!(rejection.limit, Limit::Renders);
+        // The first render leaves the window 40s later
+        assert_eq!(rejection.retry_after_secs, 41);
+
+        usage
+            .admit("org", &limits, 0, now + Duration::from_secs(60), NOON)
+            .unwrap();
+        assert_eq!(usage.renders.len(), 2);
+    }
+
+    #[test]
+    fn daily_limits_reset_on_a_new_day() {
+        let limits = Limits {
+            renders: 0,
+            ..limits()
+        };
+        let (mut usage, now) = (Usage::default(), Instant::now());
+        usage.admit("org", &limits, 2, now, NOON).unwrap();
+        usage.admit("org", &limits, 3, now, NOON).unwrap();
+        let rejection = usage.admit("org", &limits, 1, now, NOON).unwrap_err();
+        assert_eq!(rejection.limit, Limit::Recipients);
+        assert_eq!(rejection.retry_after_secs, 12 * 3600);
+        // Runs without recipients only render
+        usage.admit("org", &limits, 0, now, NOON).unwrap();
+
+        usage.admit("org", &limits, 5, now, NOON + 86400).unwrap();
+        assert_eq!((usage.emails, usage.recipients), (1, 5));
+    }
+
+    #[test]
+    fn emails_and_attachments_are_limited_per_day() {
+        let limits = Limits {
+            renders: 0,
+            recipients_per_day: 0,
+            ..limits()
+        };
+        let (mut usage, now) = (Usage::default(), Instant::now());
+        for _ in 0..3 {
+            usage.admit("org", &limits, 1, now, NOON).unwrap();
+        }
+        let rejection = usage.admit("org", &limits, 1, now, NOON).unwrap_err();
+        assert_eq!(rejection.limit, Limit::Emails);
+
+        usage.add_attachment("org", 100, 60, NOON).unwrap();
+        let rejection = usage.add_attachment("org", 100, 41, NOON).unwrap_err();
+        assert_eq!(rejection.limit, Limit::AttachmentBytes);
+        usage.add_attachment("org", 100, 40, NOON).unwrap();
+        usage.add_attachment("org", 100, 100, NOON + 86400).unwrap();
+    }
+
+    #[test]
+    fn failed_renders_are_refunded() {
+        let limits = Limits {
+            renders: 0,
+            ..limits()
+        };
+        let (mut usage, now) = (Usage::default(), Instant::now());
+        let failed = usage.admit("org", &limits, 5, now, NOON).unwrap();
+        usage.refund_render(&failed);
+        assert_eq!((usage.emails, usage.recipients), (0, 0));
+
+        // Runs admitted on the day before don't take from today's counters
+        let yesterday = usage.admit("org", &limits, 5, now, NOON).unwrap();
+        usage.admit("org", &limits, 2, now, NOON + 86400).unwrap();
+        usage.refund_render(&yesterday);
+        assert_eq!((usage.emails, usage.recipients), (1, 2));
+    }
+
+    #[test]
+    fn refunds_remove_the_render_of_the_run() {
+        let limits = Limits {
+            renders: 3,
+            ..limits()
+        };
+        let (mut usage, now) = (Usage::default(), Instant::now());
+        let long = usage.admit("org", &limits, 0, now, NOON).unwrap();
+        let later = now + Duration::from_secs(30);
+        usage.admit("org", &limits, 0, later, NOON).unwrap();
+        usage.refund_render(&long);
+        assert_eq!(usage.renders, [later]);
+
+        // A refund after the render left the window takes nothing from the newer runs
+        let old = usage.admit("org", &limits, 0, later, NOON).unwrap();
+        let next = later + Duration::from_secs(60);
+        usage.admit("org", &limits, 0, next, NOON).unwrap();
+        usage.refund_render(&old);
+        assert_eq!(usage.renders, [next]);
+    }
+
+    #[test]
+    fn rejected_attachments_give_back_the_emails() {
+        let limits = Limits {
+            emails_per_day: 1,
+            ..limits()
+        };
+        let (mut usage, now) = (Usage::default(), Instant::now());
+        let admission = usage.admit("org", &limits, 3, now, NOON).unwrap();
+        let rejection = usage.add_attachment("org", 100, 101, NOON).unwrap_err();
+        assert_eq!(rejection.limit, Limit::AttachmentBytes);
+        usage.refund_render(&admission);
+        assert_eq!(
+            (usage.emails, usage.recipients, usage.attachment_bytes),
+            (0, 0, 0)
+        );
+        usage.admit("org", &limits, 3, now, NOON).unwrap();
+    }
+
+    #[test]
+    fn unlimited_by_default() {
+        let limits = Limits {
+            renders: 0,
+            render_window: Duration::ZERO,
+            emails_per_day: 0,
+            recipients_per_day: 0,
+            attachment_bytes_per_day: 0,
+        };
+        let (mut usage, now) = (Usage::default(), Instant::now());
+        for _ in 0..100 {
+            usage.admit("org", &limits, 100, now, NOON).unwrap();
+        }
+        usage
+            .add_attachment("org", 0, usize::MAX / 2, NOON)
+            .unwrap();
+    }
+
+    #[test]
+    fn rejections_are_limit_exceeded_errors() {
+        let (mut usage, now) = (Usage::default(), Instant::now());
+        let limits = Limits {
+            renders: 1,
+            ..limits()
+        };
+        usage.admit("org", &limits, 0, now, NOON).unwrap();
+        let rejection = usage.admit("org", &limits, 0, now, NOON).unwrap_err();
+        let error = ReportError::from(rejection);
+        assert_eq!(error.code(), 1011);
+        assert_eq!(error.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
@@ -13,13 +13,23 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use actix_web::{dev::ServerHandle, middleware, web, App, HttpServer};
+use actix_web::{
+    dev::ServerHandle,
+    middleware::{self, from_fn},
+    web, App, HttpServer,
+};
 use o2_report_generator::{
-    cli,
-    config::{self, CONFIG},
-    router::{healthz, send_report},
-    ReportAttachmentDimensions,
+    auth, cli,
+    config::{self, CONFIG, SMTP_PROFILES},
+    email, grpc, jobs, limits, metrics, outbox, readiness,
+    router::{
+        download_dashboard, download_dashboard_by_query, get_job, get_job_artifact,
+        get_outbox_entry, healthz, list_outbox, metrics as metrics_handler, openapi_spec,
+        preview_report, readyz, replay_outbox_entry, send_report, validate_report_definition,
+    },
+    scheduler, telemetry, template, webhook, ReportAttachmentDimensions,
 };
+use once_cell::sync::Lazy;
 use std::net::SocketAddr;
 
 #[tokio::main]
@@ -31,6 +41,7 @@ async fn main() -> Result<(), anyhow::Error> {
         env_logger::Env::default().default_filter_or("off,o2_report_generator=info"),
     )
     .init();
+    telemetry::init()?;
 
     // cli mode
     if cli::cli().await? {
@@ -46,6 +57,22 @@ async fn main() -> Result<(), anyhow::Error> {
         panic!("Report User email and password must be specified");
     }
 
+    if auth::API_TOKENS.is_empty() {
+        panic!("ZO_REPORT_SERVER_API_TOKENS or ZO_INTERNAL_GRPC_TOKEN must be specified");
+    }
+
+    // The settings parsed into statics panic on invalid values, load them all here so
+    // misconfiguration fails on startup instead of on the first report
+    Lazy::force(&config::O2_ALLOWED_URLS);
+    Lazy::force(&template::DEFAULT_EMAIL_TEMPLATE);
+    Lazy::force(&SMTP_PROFILES);
+    Lazy::force(&email::DKIM_CONFIG);
+    Lazy::force(&email::MAIL_TRANSPORT);
+    Lazy::force(&scheduler::MISFIRE_POLICY);
+    Lazy::force(&limits::ORG_LIMITS);
+    Lazy::force(&webhook::ALLOWED_URLS);
+    metrics::init();
+
     // Log configured Chrome PDF parameters
     log::info!("Chrome PDF Configuration:");
     log::info!("  pdf_landscape: {}", CONFIG.chrome.pdf_landscape);
@@ -114,10 +141,75 @@ async fn main() -> Result<(), anyhow::Error> {
         };
         format!("{}:{}", ip, CONFIG.http.port).parse()?
     };
+    let gaddr: SocketAddr = if CONFIG.http.ipv6_enabled {
+        format!("[::]:{}", CONFIG.grpc.port).parse()?
+    } else {
+        let ip = if !CONFIG.grpc.addr.is_empty() {
+            CONFIG.grpc.addr.clone()
+        } else {
+            "0.0.0.0".to_string()
+        };
+        format!("{}:{}", ip, CONFIG.grpc.port).parse()?
+    };
+    if CONFIG.jobs.persist {
+        jobs::init();
+    }
+    tokio::task::spawn(jobs::run_cleanup());
+
+    // Retry the emails left in the outbox, including the ones from before a restart
+    if CONFIG.outbox.enabled {
+        tokio::task::spawn(outbox::run());
+    }
+
+    if CONFIG.scheduler.enabled {
+        tokio::task::spawn(scheduler::run());
+    }
+
+    tokio::task::spawn(readiness::run());
+
+    // The gRPC server runs alongside the HTTP server and stops with it
+    let (grpc_stop, grpc_stopped) = tokio::sync::oneshot::channel::<()>();
+    let grpc_server = if CONFIG.grpc.internal_grpc_token.is_empty() {
+        log::warn!("ZO_INTERNAL_GRPC_TOKEN is not set, the gRPC server is disabled");
+        None
+    } else {
+        log::info!("starting gRPC server at: {}", gaddr);
+        Some(tokio::task::spawn(async move {
+            let shutdown = async {
+                let _ = grpc_stopped.await;
+            };
+            if let Err(e) = grpc::serve(gaddr, shutdown).await {
+                log::error!("gRPC server error: {e}");
+            }
+        }))
+    };
+
     log::info!("starting HTTP server at: {}", haddr);
     let server = HttpServer::new(move || {
         App::new()
-            .service(web::scope("/api").service(send_report).service(healthz))
+            .service(
+                web::scope("/api")
+                    .wrap(from_fn(auth::check_token))
+                    .service(send_report)
+                    .service(preview_report)
+                    .service(validate_report_definition)
+                    .service(download_dashboard)
+                    .service(download_dashboard_by_query)
+                    .service(get_job)
+                    .service(get_job_artifact)
+                    .service(healthz)
+                    .service(readyz)
+                    .service(openapi_spec)
+                    .service(list_outbox)
+                    .service(get_outbox_entry)
+                    .service(replay_outbox_entry),
+            )
+            .service(
+                web::scope("/metrics")
+                    .wrap(from_fn(auth::check_metrics_token))
+                    .service(metrics_handler),
+            )
+            .wrap(from_fn(telemetry::trace_request))
             .wrap(middleware::Logger::new(
                 r#"%a "%r" %s %b "%{Content-Length}i" "%{Referer}i" "%{User-Agent}i" %T"#,
             ))
@@ -131,6 +223,12 @@ async fn main() -> Result<(), anyhow::Error> {
     });
     server.await?;
     log::info!("HTTP server stopped");
+    let _ = grpc_stop.send(());
+    if let Some(grpc_server) = grpc_server {
+        grpc_server.await?;
+        log::info!("gRPC server stopped");
+    }
+    telemetry::shutdown();
     Ok(())
 }
 
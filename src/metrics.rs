@@ -0,0 +1,230 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::render_queue;
+use once_cell::sync::Lazy;
+use prometheus::{
+    core::Collector, exponential_buckets, HistogramOpts, HistogramVec, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+use std::time::Instant;
+
+const NAMESPACE: &str = "zo_report";
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+/// Rendered reports by org, report type and outcome, `success` or `failure`
+pub static REPORTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("reports_total", "Rendered reports").namespace(NAMESPACE),
+            &["org_id", "report_type", "outcome"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Duration of the phases of a report run, see [`Phase`]
+pub static PHASE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "phase_duration_seconds",
+                "Duration of the report run phases",
+            )
+            .namespace(NAMESPACE)
+            .buckets(vec![
+                0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0,
+            ]),
+            &["phase"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static ACTIVE_BROWSERS: Lazy<IntGauge> = Lazy::new(|| {
+    register(
+        IntGauge::with_opts(
+            Opts::new("active_browsers", "Running headless browsers").namespace(NAMESPACE),
+        )
+        .unwrap(),
+    )
+});
+
+static QUEUE_RUNNING: Lazy<IntGauge> = Lazy::new(|| {
+    register(
+        IntGauge::with_opts(
+            Opts::new("render_queue_running", "Renders holding a render slot").namespace(NAMESPACE),
+        )
+        .unwrap(),
+    )
+});
+
+static QUEUE_WAITING: Lazy<IntGauge> = Lazy::new(|| {
+    register(
+        IntGauge::with_opts(
+            Opts::new("render_queue_waiting", "Renders waiting for a render slot")
+                .namespace(NAMESPACE),
+        )
+        .unwrap(),
+    )
+});
+
+/// Size of the rendered report files by report type
+pub static ATTACHMENT_SIZE: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new("attachment_size_bytes", "Size of the rendered report files")
+                .namespace(NAMESPACE)
+                .buckets(exponential_buckets(16384.0, 4.0, 8).unwrap()),
+            &["report_type"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Dashboard panel loads by outcome, `success` or `timeout`
+pub static PANEL_LOADS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("panel_loads_total", "Dashboard panel loads").namespace(NAMESPACE),
+            &["outcome"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Report email recipients by org and delivery status
+pub static EMAILS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("emails_total", "Report email recipients by delivery status")
+                .namespace(NAMESPACE),
+            &["org_id", "status"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Reports rejected by the limits of their org, by org and limit
+pub static LIMIT_REJECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("limit_rejections_total", "Reports rejected by an org limit")
+                .namespace(NAMESPACE),
+            &["org_id", "limit"],
+        )
+        .unwrap(),
+    )
+});
+
+fn register<T: Collector + Clone + 'static>(metric: T) -> T {
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Error registering metric");
+    metric
+}
+
+/// Registers all metrics, so they are exported before they are first used
+pub fn init() {
+    Lazy::force(&REPORTS);
+    Lazy::force(&PHASE_DURATION);
+    Lazy::force(&ACTIVE_BROWSERS);
+    Lazy::force(&QUEUE_RUNNING);
+    Lazy::force(&QUEUE_WAITING);
+    Lazy::force(&ATTACHMENT_SIZE);
+    Lazy::force(&PANEL_LOADS);
+    Lazy::force(&EMAILS);
+    Lazy::force(&LIMIT_REJECTIONS);
+}
+
+/// Phases of a report run
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    BrowserLaunch,
+    Login,
+    Navigation,
+    PanelLoad,
+    Capture,
+    SmtpSend,
+}
+
+impl Phase {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Phase::BrowserLaunch => "browser_launch",
+            Phase::Login => "login",
+            Phase::Navigation => "navigation",
+            Phase::PanelLoad => "panel_load",
+            Phase::Capture => "capture",
+            Phase::SmtpSend => "smtp_send",
+        }
+    }
+
+    /// Starts timing the phase, which is also traced as a span of its own
+    pub fn start(self) -> PhaseTimer {
+        PhaseTimer {
+            phase: self,
+            started: Instant::now(),
+            _span: tracing::info_span!("phase", otel.name = self.as_str()),
+        }
+    }
+}
+
+/// A running phase, see [`Phase::start`]. Phases which are not finished are not recorded
+/// in the metrics, their span ends when dropped.
+pub struct PhaseTimer {
+    phase: Phase,
+    started: Instant,
+    _span: tracing::Span,
+}
+
+impl PhaseTimer {
+    pub fn finish(self) {
+        PHASE_DURATION
+            .with_label_values(&[self.phase.as_str()])
+            .observe(self.started.elapsed().as_secs_f64());
+    }
+}
+
+/// Counts a running browser until dropped
+pub struct BrowserGuard(());
+
+impl BrowserGuard {
+    pub fn new() -> Self {
+        ACTIVE_BROWSERS.inc();
+        Self(())
+    }
+}
+
+impl Default for BrowserGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for BrowserGuard {
+    fn drop(&mut self) {
+        ACTIVE_BROWSERS.dec();
+    }
+}
+
+/// Encodes all metrics in the Prometheus text format
+pub fn gather() -> Result<String, anyhow::Error> {
+    let (running, waiting) = render_queue::stats();
+    QUEUE_RUNNING.set(running as i64);
+    QUEUE_WAITING.set(waiting as i64);
+    Ok(TextEncoder::new().encode_to_string(&REGISTRY.gather())?)
+}
@@ -0,0 +1,140 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use tokio::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Tokens are refreshed this long before they expire
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+/// Lifetime assumed when the token endpoint does not return `expires_in`
+const DEFAULT_EXPIRES_IN_SECS: u64 = 3600;
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .expect("Error building the OAuth2 http client")
+});
+
+/// OAuth2 client settings used to obtain XOAUTH2 access tokens
+#[derive(Deserialize, Debug, Clone)]
+pub struct OAuth2Config {
+    pub token_url: String,
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+    /// Uses the refresh-token flow when set, else the client-credentials flow
+    #[serde(default)]
+    pub refresh_token: String,
+    /// Space separated scopes
+    #[serde(default)]
+    pub scope: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+    /// Set when the server rotates the refresh token
+    refresh_token: Option<String>,
+}
+
+struct TokenState {
+    access_token: Option<(String, Instant)>,
+    refresh_token: String,
+}
+
+/// Fetches access tokens from the token endpoint and caches them until shortly
+/// before they expire
+pub struct TokenProvider {
+    config: OAuth2Config,
+    state: Mutex<TokenState>,
+}
+
+impl TokenProvider {
+    pub fn new(config: OAuth2Config) -> Self {
+        let state = TokenState {
+            access_token: None,
+            refresh_token: config.refresh_token.clone(),
+        };
+        Self {
+            config,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Returns the cached access token, fetching a new one when it is about to expire
+    pub async fn access_token(&self) -> Result<String, anyhow::Error> {
+        let mut state = self.state.lock().await;
+        if let Some((token, expires_at)) = &state.access_token {
+            if *expires_at > Instant::now() + EXPIRY_MARGIN {
+                return Ok(token.clone());
+            }
+        }
+
+        let response = self.request_token(&state.refresh_token).await?;
+        let expires_in = response.expires_in.unwrap_or(DEFAULT_EXPIRES_IN_SECS);
+        log::debug!(
+            "fetched OAuth2 access token from {}, expires in {expires_in}s",
+            self.config.token_url
+        );
+        if let Some(refresh_token) = response.refresh_token {
+            state.refresh_token = refresh_token;
+        }
+        state.access_token = Some((
+            response.access_token.clone(),
+            Instant::now() + Duration::from_secs(expires_in),
+        ));
+        Ok(response.access_token)
+    }
+
+    async fn request_token(&self, refresh_token: &str) -> Result<TokenResponse, anyhow::Error> {
+        let mut params = vec![("client_id", self.config.client_id.as_str())];
+        if !self.config.client_secret.is_empty() {
+            params.push(("client_secret", &self.config.client_secret));
+        }
+        if refresh_token.is_empty() {
+            params.push(("grant_type", "client_credentials"));
+        } else {
+            params.push(("grant_type", "refresh_token"));
+            params.push(("refresh_token", refresh_token));
+        }
+        if !self.config.scope.is_empty() {
+            params.push(("scope", &self.config.scope));
+        }
+
+        let response = HTTP_CLIENT
+            .post(&self.config.token_url)
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Error requesting OAuth2 token: {e}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!(
+                "OAuth2 token endpoint returned {status}: {body}"
+            ));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("Error parsing OAuth2 token response: {e}"))
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::router;
+use once_cell::sync::Lazy;
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+/// The OpenAPI document, served on `/api/openapi.json`
+pub static SPEC: Lazy<String> = Lazy::new(|| {
+    ApiDoc::openapi()
+        .to_pretty_json()
+        .expect("Error serializing the OpenAPI document")
+});
+
+/// OpenAPI 3 document of the http api. The schemas are derived from the payload types and
+/// follow their serde attributes, so the document changes with the code.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "OpenObserve report server",
+        description = "Renders OpenObserve dashboards as pdf, png or csv reports and emails them",
+        license(name = "AGPL-3.0", url = "https://www.gnu.org/licenses/agpl-3.0.html")
+    ),
+    servers((url = "/api")),
+    paths(
+        router::send_report,
+        router::preview_report,
+        router::validate_report_definition,
+        router::download_dashboard,
+        router::download_dashboard_by_query,
+        router::get_job,
+        router::get_job_artifact,
+        router::list_outbox,
+        router::get_outbox_entry,
+        router::replay_outbox_entry,
+        router::healthz,
+        router::readyz,
+        router::openapi_spec,
+    ),
+    modifiers(&ApiToken),
+    security(("api_token" = [])),
+    tags(
+        (name = "reports", description = "Send, preview and validate reports"),
+        (name = "dashboards", description = "Download dashboards as report files"),
+        (name = "jobs", description = "Async report runs"),
+        (name = "outbox", description = "Report emails which are delivered again"),
+        (name = "health", description = "Health of the server, open without a token"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Bearer token of `ZO_REPORT_SERVER_API_TOKENS`
+struct ApiToken;
+
+impl Modify for ApiToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_token",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("One of ZO_REPORT_SERVER_API_TOKENS"))
+                    .build(),
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn spec() -> Value {
+        serde_json::to_value(ApiDoc::openapi()).unwrap()
+    }
+
+    #[test]
+    fn every_route_has_a_path() {
+        let spec = spec();
+        // Routes of the `/api` scope in main.rs, relative to the server url
+        for (path, method) in [
+            ("/{org_id}/reports/{name}/send", "put"),
+            ("/{org_id}/reports/{name}/preview", "put"),
+            ("/{org_id}/reports/{name}/validate", "put"),
+            ("/{org_id}/dashboards/download", "post"),
+            ("/{org_id}/dashboards/{dashboard}/download", "get"),
+            ("/jobs/{id}", "get"),
+            ("/jobs/{id}/artifact", "get"),
+            ("/outbox", "get"),
+            ("/outbox/{id}", "get"),
+            ("/outbox/{id}/replay", "post"),
+            ("/healthz", "get"),
+            ("/readyz", "get"),
+            ("/openapi.json", "get"),
+        ] {
+            assert!(
+                spec["paths"][path][method].is_object(),
+                "{method} {path} is missing"
+            );
+        }
+    }
+
+    #[test]
+    fn payload_schemas_follow_serde() {
+        let spec = spec();
+        let schemas = &spec["components"]["schemas"];
+        assert!(schemas["Report"].is_object());
+
+        let recipients = &schemas["EmailDetails"]["properties"]["recipients"];
+        assert!(recipients["description"]
+            .as_str()
+            .unwrap()
+            .contains("`recepients`"));
+
+        assert_eq!(
+            schemas["ReportType"]["enum"],
+            serde_json::json!(["pdf", "cache", "png", "csv"])
+        );
+        assert_eq!(
+            schemas["EmailAttachmentType"]["enum"],
+            serde_json::json!(["standard", "inline"])
+        );
+    }
+}
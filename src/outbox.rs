@@ -0,0 +1,676 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{
+    config::{CONFIG, DEFAULT_SMTP_PROFILE},
+    email::{self, DeliveryStatus, RecipientStatus},
+    SmtpConfig,
+};
+use lettre::{address::Address, AsyncSmtpTransport, Message, Tokio1Executor};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, path::PathBuf, str::FromStr, sync::Mutex};
+use tokio::time::{sleep, Duration};
+use utoipa::ToSchema;
+
+const MAX_RETRY_BACKOFF_SECS: u64 = 3600;
+
+/// The outbox of `ZO_OUTBOX_DIR`
+static OUTBOX: Lazy<Outbox> = Lazy::new(|| Outbox {
+    dir: PathBuf::from(&CONFIG.outbox.dir),
+    max_attempts: CONFIG.outbox.max_attempts,
+    retry_backoff_secs: CONFIG.outbox.retry_backoff_secs,
+    retention_hours: CONFIG.outbox.retention_hours,
+    client: None,
+});
+
+/// Ids of the entries which are being delivered by this process
+static IN_FLIGHT: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxState {
+    /// Some recipients are still to be delivered
+    Pending,
+    /// Delivered to every recipient
+    Sent,
+    /// Delivery finished, but some recipients did not receive the email
+    Failed,
+}
+
+impl FromStr for OutboxState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "sent" => Ok(Self::Sent),
+            "failed" => Ok(Self::Failed),
+            _ => Err(anyhow::anyhow!(
+                "Invalid outbox state {s}, expected pending, sent or failed"
+            )),
+        }
+    }
+}
+
+/// An email in the outbox. The email is stored in `ZO_OUTBOX_DIR` as `<id>.eml`
+/// and its delivery state as `<id>.json`, so it survives SMTP outages and restarts.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct OutboxEntry {
+    pub id: String,
+    pub org_id: String,
+    pub report_name: String,
+    pub state: OutboxState,
+    /// Creation time in microseconds
+    pub created_at: i64,
+    /// Last update time in microseconds
+    pub updated_at: i64,
+    /// Number of delivery rounds made for this entry
+    pub attempts: u32,
+    /// Earliest time of the next delivery round in microseconds
+    pub next_attempt_at: i64,
+    pub from: Option<String>,
+    /// SMTP profile the email is delivered with
+    #[serde(default = "default_smtp_profile")]
+    pub smtp_profile: String,
+    /// Recipients which have not received the email yet
+    pub pending: Vec<String>,
+    /// Latest delivery result of every recipient
+    pub recipients: Vec<RecipientStatus>,
+}
+
+fn default_smtp_profile() -> String {
+    DEFAULT_SMTP_PROFILE.to_string()
+}
+
+/// Removes the entry from [`IN_FLIGHT`] when the delivery is done
+struct InFlightGuard(String);
+
+impl InFlightGuard {
+    fn acquire(id: &str) -> Option<Self> {
+        if IN_FLIGHT.lock().unwrap().insert(id.to_string()) {
+            Some(Self(id.to_string()))
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Directory and retry settings of an outbox
+struct Outbox {
+    dir: PathBuf,
+    max_attempts: u32,
+    retry_backoff_secs: u64,
+    retention_hours: u64,
+    /// Transport used for every entry instead of the transport of its SMTP profile
+    client: Option<AsyncSmtpTransport<Tokio1Executor>>,
+}
+
+/// Entry ids are generated uuids, anything else is rejected to keep lookups inside the outbox dir
+fn validate_id(id: &str) -> Result<(), anyhow::Error> {
+    uuid::Uuid::parse_str(id)
+        .map(|_| ())
+        .map_err(|_| anyhow::anyhow!("Invalid outbox entry id {id}"))
+}
+
+/// Writes the file through a temp file next to it, so a crash never leaves a truncated file
+pub(crate) async fn write_atomic(path: PathBuf, data: &[u8]) -> Result<(), anyhow::Error> {
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    tokio::fs::write(&tmp_path, data).await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    Ok(())
+}
+
+/// Stores the email in the outbox and delivers it right away.
+/// Recipients failing with transient errors are retried later by [`run`].
+pub async fn send(
+    org_id: &str,
+    report_name: &str,
+    message: &Message,
+    config: &SmtpConfig,
+) -> Result<Vec<RecipientStatus>, anyhow::Error> {
+    OUTBOX.send(org_id, report_name, message, config).await
+}
+
+/// Lists the outbox entries, oldest first
+pub async fn list(state: Option<OutboxState>) -> Result<Vec<OutboxEntry>, anyhow::Error> {
+    OUTBOX.list(state).await
+}
+
+pub async fn get(id: &str) -> Result<Option<OutboxEntry>, anyhow::Error> {
+    OUTBOX.get(id).await
+}
+
+/// Delivers the entry again to the recipients which did not receive it,
+/// or to every recipient when `all` is set.
+pub async fn replay(id: &str, all: bool) -> Result<Option<OutboxEntry>, anyhow::Error> {
+    OUTBOX.replay(id, all).await
+}
+
+/// Retries the pending entries of the outbox and removes finished entries after the
+/// retention period. Runs until the process exits.
+pub async fn run() {
+    log::info!("outbox sender started for {}", CONFIG.outbox.dir);
+    loop {
+        if let Err(e) = OUTBOX.process_pending().await {
+            log::error!("Error processing the outbox: {e}");
+        }
+        sleep(Duration::from_secs(CONFIG.outbox.poll_interval_secs)).await;
+    }
+}
+
+impl Outbox {
+    fn entry_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+
+    fn message_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.eml"))
+    }
+
+    async fn save(&self, entry: &OutboxEntry) -> Result<(), anyhow::Error> {
+        write_atomic(
+            self.entry_path(&entry.id),
+            &serde_json::to_vec_pretty(entry)?,
+        )
+        .await
+    }
+
+    async fn client(
+        &self,
+        profile: &str,
+    ) -> Result<AsyncSmtpTransport<Tokio1Executor>, anyhow::Error> {
+        match &self.client {
+            Some(client) => Ok(client.clone()),
+            None => SmtpConfig::from_profile(profile)?.client().await,
+        }
+    }
+
+    async fn send(
+        &self,
+        org_id: &str,
+        report_name: &str,
+        message: &Message,
+        config: &SmtpConfig,
+    ) -> Result<Vec<RecipientStatus>, anyhow::Error> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let now = chrono::Utc::now().timestamp_micros();
+        let envelope = message.envelope();
+        let mut entry = OutboxEntry {
+            id: uuid::Uuid::now_v7().to_string(),
+            org_id: org_id.to_string(),
+            report_name: report_name.to_string(),
+            state: OutboxState::Pending,
+            created_at: now,
+            updated_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+            from: envelope.from().map(|a| a.to_string()),
+            smtp_profile: config.profile.clone(),
+            pending: envelope.to().iter().map(|a| a.to_string()).collect(),
+            recipients: vec![],
+        };
+        let _guard = InFlightGuard::acquire(&entry.id);
+        write_atomic(self.message_path(&entry.id), &message.formatted()).await?;
+        self.save(&entry).await?;
+        log::info!(
+            "[{org_id}/{report_name}] email stored in outbox entry {}",
+            entry.id
+        );
+
+        self.deliver(&mut entry).await?;
+        Ok(entry.recipients)
+    }
+
+    /// Delivers the email to the pending recipients of the entry and stores the outcome
+    async fn deliver(&self, entry: &mut OutboxEntry) -> Result<(), anyhow::Error> {
+        let client = self.client(&entry.smtp_profile).await?;
+        let email = tokio::fs::read(self.message_path(&entry.id)).await?;
+        let from = entry.from.as_deref().map(Address::from_str).transpose()?;
+
+        let mut results = vec![];
+        for recipient in std::mem::take(&mut entry.pending) {
+            results.push(match Address::from_str(&recipient) {
+                Ok(address) => email::deliver_to(&client, from.clone(), &address, &email).await,
+                Err(e) => (RecipientStatus::failed(&recipient, 0, e.to_string()), false),
+            });
+        }
+        self.finish_round(entry, results, chrono::Utc::now().timestamp_micros());
+        self.save(entry).await
+    }
+
+    /// Records the results of a delivery round, with whether each failure was transient,
+    /// and moves the entry to its next state
+    fn finish_round(
+        &self,
+        entry: &mut OutboxEntry,
+        results: Vec<(RecipientStatus, bool)>,
+        now: i64,
+    ) {
+        for (mut status, retryable) in results {
+            if status.status == DeliveryStatus::Failed && retryable {
+                status.status = DeliveryStatus::Queued;
+                entry.pending.push(status.recipient.clone());
+            }
+            match entry
+                .recipients
+                .iter_mut()
+                .find(|r| r.recipient == status.recipient)
+            {
+                Some(previous) => *previous = status,
+                None => entry.recipients.push(status),
+            }
+        }
+
+        entry.attempts += 1;
+        entry.updated_at = now;
+        entry.state = if !entry.pending.is_empty() && entry.attempts < self.max_attempts {
+            let backoff = self
+                .retry_backoff_secs
+                .saturating_mul(1 << (entry.attempts - 1).min(16))
+                .min(MAX_RETRY_BACKOFF_SECS);
+            entry.next_attempt_at = now + Duration::from_secs(backoff).as_micros() as i64;
+            log::warn!(
+                "[{}/{}] outbox entry {} has {} pending recipients, retrying in {backoff}s",
+                entry.org_id,
+                entry.report_name,
+                entry.id,
+                entry.pending.len()
+            );
+            OutboxState::Pending
+        } else if entry
+            .recipients
+            .iter()
+            .all(|r| r.status == DeliveryStatus::Sent)
+        {
+            OutboxState::Sent
+        } else {
+            for r in entry.recipients.iter_mut() {
+                if r.status == DeliveryStatus::Queued {
+                    r.status = DeliveryStatus::Failed;
+                }
+            }
+            OutboxState::Failed
+        };
+    }
+
+    async fn list(&self, state: Option<OutboxState>) -> Result<Vec<OutboxEntry>, anyhow::Error> {
+        let mut entries = vec![];
+        let mut dir = match tokio::fs::read_dir(&self.dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(file) = dir.next_entry().await? {
+            let path = file.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            match serde_json::from_slice::<OutboxEntry>(&tokio::fs::read(&path).await?) {
+                Ok(entry) if state.is_none_or(|s| s == entry.state) => entries.push(entry),
+                Ok(_) => {}
+                Err(e) => log::error!("Error reading outbox entry {}: {e}", path.display()),
+            }
+        }
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(entries)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<OutboxEntry>, anyhow::Error> {
+        validate_id(id)?;
+        match tokio::fs::read(self.entry_path(id)).await {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn replay(&self, id: &str, all: bool) -> Result<Option<OutboxEntry>, anyhow::Error> {
+        let Some(_guard) = InFlightGuard::acquire(id) else {
+            return Err(anyhow::anyhow!("Outbox entry {id} is being delivered"));
+        };
+        let Some(mut entry) = self.get(id).await? else {
+            return Ok(None);
+        };
+        entry.pending = entry
+            .recipients
+            .iter()
+            .filter(|r| all || r.status != DeliveryStatus::Sent)
+            .map(|r| r.recipient.clone())
+            .chain(entry.pending.drain(..))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        entry.attempts = 0;
+        entry.state = OutboxState::Pending;
+        log::info!(
+            "[{}/{}] replaying outbox entry {id} to {} recipients",
+            entry.org_id,
+            entry.report_name,
+            entry.pending.len()
+        );
+        self.deliver(&mut entry).await?;
+        Ok(Some(entry))
+    }
+
+    async fn process_pending(&self) -> Result<(), anyhow::Error> {
+        let now = chrono::Utc::now().timestamp_micros();
+        let retention = Duration::from_secs(self.retention_hours * 3600).as_micros() as i64;
+        for entry in self.list(None).await? {
+            let is_due = entry.state == OutboxState::Pending && entry.next_attempt_at <= now;
+            let is_expired =
+                entry.state != OutboxState::Pending && now - entry.updated_at > retention;
+            if !is_due && !is_expired {
+                continue;
+            }
+            let Some(_guard) = InFlightGuard::acquire(&entry.id) else {
+                continue;
+            };
+            // The listing may be stale, a replay or an earlier pass can have delivered the entry
+            let mut entry = match self.get(&entry.id).await {
+                Ok(Some(entry)) => entry,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::error!("Error reading outbox entry {}: {e}", entry.id);
+                    continue;
+                }
+            };
+            if entry.state != OutboxState::Pending {
+                if now - entry.updated_at > retention {
+                    self.remove(&entry.id).await;
+                }
+                continue;
+            }
+            if entry.next_attempt_at > now {
+                continue;
+            }
+            if let Err(e) = self.deliver(&mut entry).await {
+                log::error!("Error delivering outbox entry {}: {e}", entry.id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a finished entry after the retention period, errors are logged so the other
+    /// entries are still processed
+    async fn remove(&self, id: &str) {
+        log::debug!("removing outbox entry {id}");
+        for path in [self.entry_path(id), self.message_path(id)] {
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::error!("Error removing outbox file {}: {e}", path.display());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::tests::smtp_server;
+    use std::path::Path;
+
+    fn outbox(dir: &Path, client: Option<AsyncSmtpTransport<Tokio1Executor>>) -> Outbox {
+        Outbox {
+            dir: dir.to_path_buf(),
+            max_attempts: 3,
+            retry_backoff_secs: 60,
+            retention_hours: 1,
+            client,
+        }
+    }
+
+    fn status(recipient: &str, status: DeliveryStatus) -> RecipientStatus {
+        RecipientStatus {
+            recipient: recipient.to_string(),
+            status,
+            attempts: 1,
+            smtp_code: None,
+            error: None,
+        }
+    }
+
+    /// Stores an entry with its email, last updated `age_secs` ago
+    async fn store(
+        outbox: &Outbox,
+        state: OutboxState,
+        pending: &[&str],
+        recipients: Vec<RecipientStatus>,
+        age_secs: i64,
+    ) -> OutboxEntry {
+        let updated_at = chrono::Utc::now().timestamp_micros() - age_secs * 1_000_000;
+        let entry = OutboxEntry {
+            id: uuid::Uuid::now_v7().to_string(),
+            org_id: "default".to_string(),
+            report_name: "weekly".to_string(),
+            state,
+            created_at: updated_at,
+            updated_at,
+            attempts: 1,
+            next_attempt_at: updated_at,
+            from: Some("reports@example.com".to_string()),
+            smtp_profile: DEFAULT_SMTP_PROFILE.to_string(),
+            pending: pending.iter().map(|r| r.to_string()).collect(),
+            recipients,
+        };
+        tokio::fs::create_dir_all(&outbox.dir).await.unwrap();
+        write_atomic(
+            outbox.message_path(&entry.id),
+            b"Subject: weekly\r\n\r\nreport\r\n",
+        )
+        .await
+        .unwrap();
+        outbox.save(&entry).await.unwrap();
+        entry
+    }
+
+    fn entry(recipients: &[&str]) -> OutboxEntry {
+        OutboxEntry {
+            id: uuid::Uuid::now_v7().to_string(),
+            org_id: "default".to_string(),
+            report_name: "weekly".to_string(),
+            state: OutboxState::Pending,
+            created_at: 0,
+            updated_at: 0,
+            attempts: 0,
+            next_attempt_at: 0,
+            from: None,
+            smtp_profile: DEFAULT_SMTP_PROFILE.to_string(),
+            pending: recipients.iter().map(|r| r.to_string()).collect(),
+            recipients: vec![],
+        }
+    }
+
+    #[test]
+    fn transient_failures_stay_pending_until_the_last_attempt() {
+        let outbox = outbox(Path::new("unused"), None);
+        let mut entry = entry(&["a@example.com", "b@example.com"]);
+        entry.pending.clear();
+
+        let results = vec![
+            (status("a@example.com", DeliveryStatus::Sent), false),
+            (status("b@example.com", DeliveryStatus::Failed), true),
+        ];
+        outbox.finish_round(&mut entry, results, 1_000);
+        assert_eq!(entry.state, OutboxState::Pending);
+        assert_eq!(entry.attempts, 1);
+        assert_eq!(entry.pending, vec!["b@example.com"]);
+        assert_eq!(entry.recipients[1].status, DeliveryStatus::Queued);
+        assert_eq!(entry.next_attempt_at, 1_000 + 60_000_000);
+
+        entry.pending.clear();
+        let results = vec![(status("b@example.com", DeliveryStatus::Failed), true)];
+        outbox.finish_round(&mut entry, results, 2_000);
+        assert_eq!(entry.state, OutboxState::Pending);
+        assert_eq!(entry.next_attempt_at, 2_000 + 120_000_000);
+
+        entry.pending.clear();
+        let results = vec![(status("b@example.com", DeliveryStatus::Failed), true)];
+        outbox.finish_round(&mut entry, results, 3_000);
+        assert_eq!(entry.state, OutboxState::Failed);
+        assert_eq!(entry.attempts, 3);
+        assert_eq!(entry.recipients.len(), 2);
+        assert_eq!(entry.recipients[0].status, DeliveryStatus::Sent);
+        assert_eq!(entry.recipients[1].status, DeliveryStatus::Failed);
+    }
+
+    #[test]
+    fn rounds_finish_as_sent_or_failed() {
+        let outbox = outbox(Path::new("unused"), None);
+
+        let mut sent = entry(&[]);
+        let results = vec![(status("a@example.com", DeliveryStatus::Sent), false)];
+        outbox.finish_round(&mut sent, results, 1_000);
+        assert_eq!(sent.state, OutboxState::Sent);
+        assert_eq!(sent.updated_at, 1_000);
+
+        let mut failed = entry(&[]);
+        let results = vec![
+            (status("a@example.com", DeliveryStatus::Sent), false),
+            (status("b@example.com", DeliveryStatus::Failed), false),
+        ];
+        outbox.finish_round(&mut failed, results, 1_000);
+        assert_eq!(failed.state, OutboxState::Failed);
+        assert!(failed.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_stores_the_email_and_its_delivery() {
+        let dir = tempfile::tempdir().unwrap();
+        let (client, received) = smtp_server().await;
+        let outbox = outbox(dir.path(), Some(client));
+        let message = Message::builder()
+            .from("reports@example.com".parse().unwrap())
+            .to("a@example.com".parse().unwrap())
+            .to("rejected@example.com".parse().unwrap())
+            .subject("weekly")
+            .body("report".to_string())
+            .unwrap();
+        let config = SmtpConfig {
+            profile: DEFAULT_SMTP_PROFILE.to_string(),
+            from_email: "reports@example.com".to_string(),
+            reply_to: String::new(),
+        };
+
+        let recipients = outbox
+            .send("default", "weekly", &message, &config)
+            .await
+            .unwrap();
+        assert_eq!(recipients[0].status, DeliveryStatus::Sent);
+        assert_eq!(recipients[1].status, DeliveryStatus::Failed);
+        assert_eq!(recipients[1].smtp_code.as_deref(), Some("550"));
+        assert_eq!(*received.lock().unwrap(), vec!["a@example.com"]);
+
+        let entries = outbox.list(None).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.state, OutboxState::Failed);
+        assert_eq!(entry.attempts, 1);
+        assert!(entry.pending.is_empty());
+        let email = tokio::fs::read(outbox.message_path(&entry.id))
+            .await
+            .unwrap();
+        assert_eq!(email, message.formatted());
+        assert!(outbox
+            .list(Some(OutboxState::Sent))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn replay_delivers_to_the_failed_recipients_or_everyone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (client, received) = smtp_server().await;
+        let outbox = outbox(dir.path(), Some(client));
+        let stored = store(
+            &outbox,
+            OutboxState::Failed,
+            &[],
+            vec![
+                status("a@example.com", DeliveryStatus::Sent),
+                status("b@example.com", DeliveryStatus::Failed),
+            ],
+            0,
+        )
+        .await;
+
+        let entry = outbox.replay(&stored.id, false).await.unwrap().unwrap();
+        assert_eq!(entry.state, OutboxState::Sent);
+        assert_eq!(entry.attempts, 1);
+        assert_eq!(*received.lock().unwrap(), vec!["b@example.com"]);
+        let saved = outbox.get(&stored.id).await.unwrap().unwrap();
+        assert_eq!(saved.state, OutboxState::Sent);
+
+        received.lock().unwrap().clear();
+        outbox.replay(&stored.id, true).await.unwrap().unwrap();
+        let mut all = received.lock().unwrap().clone();
+        all.sort();
+        assert_eq!(all, vec!["a@example.com", "b@example.com"]);
+
+        let unknown = uuid::Uuid::now_v7().to_string();
+        assert!(outbox.replay(&unknown, false).await.unwrap().is_none());
+        assert!(outbox.get("../secrets").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn process_pending_retries_due_entries_and_removes_expired_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let (client, received) = smtp_server().await;
+        let outbox = outbox(dir.path(), Some(client));
+        let sent = |r: &str| vec![status(r, DeliveryStatus::Sent)];
+        let expired = store(&outbox, OutboxState::Sent, &[], sent("a@example.com"), 7200).await;
+        let recent = store(
+            &outbox,
+            OutboxState::Failed,
+            &[],
+            sent("b@example.com"),
+            1800,
+        )
+        .await;
+        let due = store(
+            &outbox,
+            OutboxState::Pending,
+            &["c@example.com"],
+            vec![],
+            10,
+        )
+        .await;
+        let mut later = store(&outbox, OutboxState::Pending, &["d@example.com"], vec![], 0).await;
+        later.next_attempt_at = chrono::Utc::now().timestamp_micros() + 60_000_000;
+        outbox.save(&later).await.unwrap();
+
+        outbox.process_pending().await.unwrap();
+
+        assert!(outbox.get(&expired.id).await.unwrap().is_none());
+        assert!(!outbox.message_path(&expired.id).exists());
+        assert!(outbox.get(&recent.id).await.unwrap().is_some());
+        let due = outbox.get(&due.id).await.unwrap().unwrap();
+        assert_eq!(due.state, OutboxState::Sent);
+        assert_eq!(due.attempts, 2);
+        let later = outbox.get(&later.id).await.unwrap().unwrap();
+        assert_eq!(later.state, OutboxState::Pending);
+        assert_eq!(later.pending, vec!["d@example.com"]);
+        assert_eq!(*received.lock().unwrap(), vec!["c@example.com"]);
+    }
+}
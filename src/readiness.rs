@@ -0,0 +1,260 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{
+    config::{get_chrome_launch_options, CONFIG, DEFAULT_SMTP_PROFILE, SMTP_PROFILES},
+    email::{MailTransport, MAIL_TRANSPORT},
+    metrics::BrowserGuard,
+    render_queue, ReportAttachmentDimensions,
+};
+use chromiumoxide::Browser;
+use futures::{future::join_all, Future, StreamExt};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{collections::BTreeMap, sync::RwLock, time::Instant};
+use tokio::time::{sleep, Duration};
+use utoipa::ToSchema;
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(CONFIG.readiness.timeout_secs))
+        .build()
+        .expect("Error building the readiness http client")
+});
+
+/// Result of the last readiness checks, refreshed in the background by [`run`]
+static LAST: Lazy<RwLock<Option<Readiness>>> = Lazy::new(|| RwLock::new(None));
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+    /// The component is not used, e.g. SMTP with the file mail transport, or Chromium was
+    /// not checked because every render slot is busy
+    Skipped,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct ComponentStatus {
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ComponentStatus {
+    fn skipped() -> Self {
+        Self {
+            status: CheckStatus::Skipped,
+            latency_ms: None,
+            error: None,
+        }
+    }
+}
+
+/// Result of the readiness checks, returned by `/readyz`
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    /// Check time in microseconds
+    pub checked_at: i64,
+    /// Status by component: `chromium`, `o2`, and `smtp` or `smtp:<profile>` per SMTP profile
+    pub components: BTreeMap<String, ComponentStatus>,
+}
+
+impl Readiness {
+    /// Not ready, the checks have not finished yet
+    fn pending() -> Self {
+        Self {
+            ready: false,
+            checked_at: 0,
+            components: BTreeMap::new(),
+        }
+    }
+
+    /// Leaves out the errors, which may name internal hosts or contain SMTP responses
+    pub fn without_errors(mut self) -> Self {
+        for component in self.components.values_mut() {
+            component.error = None;
+        }
+        self
+    }
+}
+
+/// Runs the check with the readiness timeout
+async fn timed(check: impl Future<Output = Result<(), anyhow::Error>>) -> ComponentStatus {
+    let started = Instant::now();
+    let timeout = Duration::from_secs(CONFIG.readiness.timeout_secs);
+    let result = match tokio::time::timeout(timeout, check).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!(
+            "Timed out after {} seconds",
+            timeout.as_secs()
+        )),
+    };
+    ComponentStatus {
+        status: if result.is_ok() {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Failed
+        },
+        latency_ms: Some(started.elapsed().as_millis() as u64),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+/// Launches and closes the browser used for the reports
+async fn check_chromium() -> Result<(), anyhow::Error> {
+    let user_tmp_dir = tempfile::tempdir()?;
+    let browser_config = get_chrome_launch_options(ReportAttachmentDimensions::default())
+        .await
+        .clone()
+        .user_data_dir(user_tmp_dir.path())
+        .build()
+        .map_err(|e| anyhow::anyhow!("Error building browser config: {e}"))?;
+    let (mut browser, mut handler) = Browser::launch(browser_config).await?;
+    let _browser_guard = BrowserGuard::new();
+    let handle = tokio::task::spawn(async move { while handler.next().await.is_some() {} });
+    browser.close().await?;
+    browser.wait().await?;
+    handle.await?;
+    Ok(())
+}
+
+/// Checks that O2 responds without a server error
+async fn check_o2() -> Result<(), anyhow::Error> {
+    let resp = HTTP_CLIENT.get(&CONFIG.common.o2_web_uri).send().await?;
+    if resp.status().is_server_error() {
+        return Err(anyhow::anyhow!("O2 responded with {}", resp.status()));
+    }
+    Ok(())
+}
+
+/// Connects and authenticates to the SMTP server of the profile
+async fn check_smtp(profile: &str) -> Result<(), anyhow::Error> {
+    let transport = SMTP_PROFILES.transport(profile).await?;
+    if !transport.test_connection().await? {
+        return Err(anyhow::anyhow!("SMTP server did not accept the connection"));
+    }
+    Ok(())
+}
+
+/// Checks Chromium in a render slot, so the probe never runs more browsers than
+/// `ZO_RENDER_MAX_CONCURRENCY`. When every slot is busy the browser is not launched and the
+/// previous result is kept.
+async fn chromium_status(previous: Option<ComponentStatus>) -> ComponentStatus {
+    match render_queue::try_acquire() {
+        Some(_permit) => timed(check_chromium()).await,
+        None => previous.unwrap_or_else(ComponentStatus::skipped),
+    }
+}
+
+/// Checks the SMTP server of every profile at once
+async fn smtp_statuses() -> Vec<(String, ComponentStatus)> {
+    join_all(SMTP_PROFILES.profiles.keys().map(|profile| async move {
+        let name = if profile == DEFAULT_SMTP_PROFILE {
+            "smtp".to_string()
+        } else {
+            format!("smtp:{profile}")
+        };
+        let status = match *MAIL_TRANSPORT {
+            MailTransport::Smtp => timed(check_smtp(profile)).await,
+            MailTransport::File | MailTransport::Stdout => ComponentStatus::skipped(),
+        };
+        (name, status)
+    }))
+    .await
+}
+
+async fn run_checks(previous: Option<&Readiness>) -> Readiness {
+    let mut components = BTreeMap::new();
+    let previous_chromium = previous.and_then(|r| r.components.get("chromium").cloned());
+    let (chromium, o2, smtp) = tokio::join!(
+        chromium_status(previous_chromium),
+        timed(check_o2()),
+        smtp_statuses()
+    );
+    components.insert("chromium".to_string(), chromium);
+    components.insert("o2".to_string(), o2);
+    components.extend(smtp);
+
+    let ready = components.values().all(|c| c.status != CheckStatus::Failed);
+    for (name, component) in components.iter() {
+        if let Some(error) = &component.error {
+            log::warn!("readiness check of {name} failed: {error}");
+        }
+    }
+    Readiness {
+        ready,
+        checked_at: chrono::Utc::now().timestamp_micros(),
+        components,
+    }
+}
+
+/// Checks Chromium, O2 and the SMTP servers every `ZO_READYZ_CACHE_SECS`, so the probes
+/// only read the last result and never wait for the checks
+pub async fn run() {
+    let interval = Duration::from_secs(CONFIG.readiness.cache_secs.max(1));
+    let mut previous = None;
+    loop {
+        let readiness = run_checks(previous.as_ref()).await;
+        *LAST.write().unwrap() = Some(readiness.clone());
+        previous = Some(readiness);
+        sleep(interval).await;
+    }
+}
+
+/// Result of the last readiness checks, not ready until the first checks have finished
+pub fn check() -> Readiness {
+    LAST.read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(Readiness::pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_left_out() {
+        let failed = ComponentStatus {
+            status: CheckStatus::Failed,
+            latency_ms: Some(12),
+            error: Some("535 authentication failed for smtp.internal".to_string()),
+        };
+        let readiness = Readiness {
+            ready: false,
+            checked_at: 1,
+            components: BTreeMap::from([("smtp".to_string(), failed)]),
+        }
+        .without_errors();
+        let smtp = &readiness.components["smtp"];
+        assert_eq!(smtp.status, CheckStatus::Failed);
+        assert!(smtp.error.is_none());
+        assert!(!serde_json::to_string(&readiness)
+            .unwrap()
+            .contains("smtp.internal"));
+    }
+
+    #[test]
+    fn not_ready_before_the_first_checks() {
+        let readiness = check();
+        assert!(!readiness.ready);
+        assert!(readiness.components.is_empty());
+    }
+}
@@ -0,0 +1,294 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::config::CONFIG;
+use once_cell::sync::Lazy;
+use std::{collections::VecDeque, sync::Mutex};
+use tokio::sync::oneshot;
+
+static QUEUE: Lazy<Mutex<QueueState>> = Lazy::new(|| Mutex::new(QueueState::default()));
+
+type Queue = &'static Mutex<QueueState>;
+
+/// Waiters of an org by id, oldest first
+type Waiters = VecDeque<(u64, oneshot::Sender<()>)>;
+
+#[derive(Default)]
+struct QueueState {
+    /// Renders holding a permit
+    running: usize,
+    /// Waiters across all orgs
+    waiting: usize,
+    /// Id of the next waiter
+    next_id: u64,
+    /// Waiters per org. Freed slots go to the org at the front, which then moves to the back,
+    /// so every org with waiters gets its turn
+    orgs: VecDeque<(String, Waiters)>,
+}
+
+impl QueueState {
+    /// Hands the slot of a finished render to the next waiter, or frees it
+    fn release(&mut self) {
+        while let Some((org_id, mut waiters)) = self.orgs.pop_front() {
+            let waiter = waiters.pop_front();
+            if !waiters.is_empty() {
+                self.orgs.push_back((org_id, waiters));
+            }
+            if let Some((_, waiter)) = waiter {
+                self.waiting -= 1;
+                if waiter.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+        self.running -= 1;
+    }
+
+    /// Removes a waiter which gave up its place before it got a slot
+    fn cancel(&mut self, org_id: &str, id: u64) {
+        let Some(pos) = self.orgs.iter().position(|(org, _)| org == org_id) else {
+            return;
+        };
+        let waiters = &mut self.orgs[pos].1;
+        let Some(i) = waiters.iter().position(|(waiter_id, _)| *waiter_id == id) else {
+            return;
+        };
+        waiters.remove(i);
+        self.waiting -= 1;
+        if waiters.is_empty() {
+            self.orgs.remove(pos);
+        }
+    }
+}
+
+/// The render queue has no room left, the request should be retried later
+#[derive(Debug)]
+pub struct QueueFull {
+    pub retry_after_secs: u64,
+}
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Too many reports are being rendered, retry in {}s",
+            self.retry_after_secs
+        )
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+/// A place in the render queue, see [`enqueue`]
+pub struct Ticket {
+    queue: Queue,
+    org_id: String,
+    id: u64,
+    /// `None` when a slot was free right away
+    rx: Option<oneshot::Receiver<()>>,
+    granted: bool,
+}
+
+impl Ticket {
+    /// Waits for a render slot
+    pub async fn wait(mut self) -> RenderPermit {
+        if let Some(rx) = self.rx.as_mut() {
+            // The sender is only dropped after handing over the slot
+            let _ = rx.await;
+        }
+        self.granted = true;
+        RenderPermit(self.queue)
+    }
+}
+
+impl Drop for Ticket {
+    /// Gives up the place in the queue when the ticket is dropped without using it, e.g. when
+    /// the request was cancelled or timed out while waiting
+    fn drop(&mut self) {
+        if self.granted {
+            return;
+        }
+        let mut state = self.queue.lock().unwrap();
+        match self.rx.as_mut() {
+            Some(rx) => {
+                rx.close();
+                // The slot may have been handed over already
+                if rx.try_recv().is_ok() {
+                    state.release();
+                } else {
+                    state.cancel(&self.org_id, self.id);
+                }
+            }
+            None => state.release(),
+        }
+    }
+}
+
+/// A render slot, freed when dropped
+pub struct RenderPermit(Queue);
+
+impl Drop for RenderPermit {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().release();
+    }
+}
+
+/// Takes a place in the render queue of the org. At most `ZO_RENDER_MAX_CONCURRENCY`
+/// reports are rendered at once, up to `ZO_RENDER_QUEUE_SIZE` more wait for a slot
+/// and anything beyond is rejected.
+pub fn enqueue(org_id: &str) -> Result<Ticket, QueueFull> {
+    enqueue_in(
+        &QUEUE,
+        org_id,
+        CONFIG.render.max_concurrency,
+        CONFIG.render.queue_size,
+    )
+}
+
+fn enqueue_in(
+    queue: Queue,
+    org_id: &str,
+    max_concurrency: usize,
+    queue_size: usize,
+) -> Result<Ticket, QueueFull> {
+    let mut state = queue.lock().unwrap();
+    if state.running < max_concurrency.max(1) && state.waiting == 0 {
+        state.running += 1;
+        return Ok(Ticket {
+            queue,
+            org_id: org_id.to_string(),
+            id: 0,
+            rx: None,
+            granted: false,
+        });
+    }
+    if state.waiting >= queue_size {
+        log::warn!(
+            "[{org_id}] render queue is full with {} waiting reports",
+            state.waiting
+        );
+        return Err(QueueFull {
+            retry_after_secs: CONFIG.render.retry_after_secs,
+        });
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let id = state.next_id;
+    state.next_id += 1;
+    state.waiting += 1;
+    match state.orgs.iter_mut().find(|(org, _)| org == org_id) {
+        Some((_, waiters)) => waiters.push_back((id, tx)),
+        None => state
+            .orgs
+            .push_back((org_id.to_string(), VecDeque::from([(id, tx)]))),
+    }
+    log::debug!(
+        "[{org_id}] report queued for rendering, {} waiting",
+        state.waiting
+    );
+    Ok(Ticket {
+        queue,
+        org_id: org_id.to_string(),
+        id,
+        rx: Some(rx),
+        granted: false,
+    })
+}
+
+/// Takes a render slot when one is free right away, without waiting in the queue
+pub fn try_acquire() -> Option<RenderPermit> {
+    let queue: Queue = &QUEUE;
+    let mut state = queue.lock().unwrap();
+    if state.running < CONFIG.render.max_concurrency.max(1) && state.waiting == 0 {
+        state.running += 1;
+        Some(RenderPermit(queue))
+    } else {
+        None
+    }
+}
+
+/// Number of running and waiting renders
+pub fn stats() -> (usize, usize) {
+    let state = QUEUE.lock().unwrap();
+    (state.running, state.waiting)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use tokio::time::{timeout, Duration};
+
+    fn new_queue() -> Queue {
+        Box::leak(Box::new(Mutex::new(QueueState::default())))
+    }
+
+    fn stats_of(queue: Queue) -> (usize, usize) {
+        let state = queue.lock().unwrap();
+        (state.running, state.waiting)
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiters_leave_the_queue() {
+        let queue = new_queue();
+        let running = enqueue_in(queue, "org", 1, 2).unwrap().wait().await;
+        let dropped = enqueue_in(queue, "org", 1, 2).unwrap();
+        let timed_out = enqueue_in(queue, "org", 1, 2).unwrap();
+        assert!(enqueue_in(queue, "org", 1, 2).is_err());
+        assert_eq!(stats_of(queue), (1, 2));
+
+        drop(dropped);
+        assert!(timeout(Duration::from_millis(10), timed_out.wait())
+            .await
+            .is_err());
+        assert_eq!(stats_of(queue), (1, 0));
+
+        let next = enqueue_in(queue, "org", 1, 2).unwrap();
+        drop(running);
+        let permit = next.wait().now_or_never().expect("slot handed over");
+        assert_eq!(stats_of(queue), (1, 0));
+        drop(permit);
+        assert_eq!(stats_of(queue), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn unused_tickets_free_their_slot() {
+        let queue = new_queue();
+        let ticket = enqueue_in(queue, "org", 1, 1).unwrap();
+        drop(ticket);
+        assert_eq!(stats_of(queue), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn freed_slots_rotate_across_orgs() {
+        let queue = new_queue();
+        let running = enqueue_in(queue, "a", 1, 10).unwrap().wait().await;
+        let a1 = enqueue_in(queue, "a", 1, 10).unwrap();
+        let a2 = enqueue_in(queue, "a", 1, 10).unwrap();
+        let b1 = enqueue_in(queue, "b", 1, 10).unwrap();
+        let a3 = enqueue_in(queue, "a", 1, 10).unwrap();
+
+        drop(running);
+        let permit = a1.wait().now_or_never().expect("a1 gets the slot");
+        drop(permit);
+        let permit = b1.wait().now_or_never().expect("b1 goes before a2");
+        drop(permit);
+        let permit = a2.wait().now_or_never().expect("a2 gets the slot");
+        drop(permit);
+        let permit = a3.wait().now_or_never().expect("a3 gets the slot");
+        drop(permit);
+        assert_eq!(stats_of(queue), (0, 0));
+    }
+}
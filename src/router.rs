@@ -15,13 +15,39 @@
 
 use crate::EmailAttachmentType::Inline;
 use crate::{
-    config::{CONFIG, SMTP_CLIENT},
-    Report, ReportType,
+    auth,
+    config::{self, CONFIG, DEFAULT_TIMEZONE},
+    email::{DeliveryStatus, RecipientStatus},
+    error::ReportError,
+    idempotency::{self, Claim, Outcome},
+    jobs::{self, Job, JobRequest},
+    limits::{self, Admission},
+    outbox::{self, OutboxEntry, OutboxState},
+    readiness::{self, Readiness},
+    render_queue::{self, Ticket},
+    telemetry,
+    validation::{self, FieldError, Validation},
+    webhook::{self, RunStatus},
+    EmailAttachmentType, EmailPreview, RenderedReport, Report, ReportDashboard,
+    ReportDashboardVariable, ReportTimerange, ReportTimerangeType, ReportType, SmtpConfig,
+};
+use actix_web::{
+    get,
+    http::{
+        header::{ContentDisposition, HeaderName, HeaderValue, RETRY_AFTER},
+        StatusCode,
+    },
+    post, put, web, HttpRequest, HttpResponse as ActixHttpResponse,
 };
-use actix_web::{get, http::StatusCode, put, web, HttpRequest, HttpResponse as ActixHttpResponse};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io::Error;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+/// Header of the idempotency key of a send request
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
 
 /// HTTP response
 /// code 200 is success
@@ -29,9 +55,9 @@ use std::io::Error;
 /// code 404 is not found
 /// code 500 is internal server error
 /// code 503 is service unavailable
-/// code >= 1000 is custom error code
+/// code >= 1000 is custom error code, see [`ReportError::code`]
 /// message is the message or error message
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct HttpResponse {
     pub code: u16,
     pub message: String,
@@ -39,6 +65,12 @@ pub struct HttpResponse {
     pub error_detail: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub trace_id: Option<String>,
+    /// Delivery result per recipient, set when a report was emailed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipients: Option<Vec<RecipientStatus>>,
+    /// Path of the written `.eml` file, set by the file mail transport
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eml_path: Option<String>,
 }
 
 impl HttpResponse {
@@ -47,7 +79,9 @@ impl HttpResponse {
             code: StatusCode::INTERNAL_SERVER_ERROR.into(),
             message: e,
             error_detail: None,
-            trace_id: None,
+            trace_id: telemetry::current_trace_id(),
+            recipients: None,
+            eml_path: None,
         }
     }
 
@@ -56,7 +90,28 @@ impl HttpResponse {
             code: StatusCode::OK.into(),
             message: msg,
             error_detail: None,
-            trace_id: None,
+            trace_id: telemetry::current_trace_id(),
+            recipients: None,
+            eml_path: None,
+        }
+    }
+
+    /// Response of a failed report run with the custom code of a [`ReportError`], or a 500.
+    /// Returns the http status of the response.
+    pub fn from_error(e: &anyhow::Error) -> (StatusCode, Self) {
+        match ReportError::find(e) {
+            Some(error) => (error.status(), Self::from_report_error(error)),
+            None => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Self::internal_server_error(e.to_string()),
+            ),
+        }
+    }
+
+    pub fn from_report_error(error: &ReportError) -> Self {
+        Self {
+            error_detail: Some(error.detail().to_string()),
+            ..Self::new(error.message().to_string(), error.code())
         }
     }
 
@@ -65,39 +120,109 @@ impl HttpResponse {
             code: status_code,
             message: msg,
             error_detail: None,
-            trace_id: None,
+            trace_id: telemetry::current_trace_id(),
+            recipients: None,
+            eml_path: None,
         }
     }
+
+    pub fn with_recipients(mut self, recipients: Vec<RecipientStatus>) -> Self {
+        self.recipients = Some(recipients);
+        self
+    }
+
+    pub fn with_eml_path(mut self, eml_path: Option<String>) -> Self {
+        self.eml_path = eml_path;
+        self
+    }
 }
 
+/// Error response of a failed report run, see [`HttpResponse::from_error`]
+fn error_response(e: &anyhow::Error) -> ActixHttpResponse {
+    let (status, body) = HttpResponse::from_error(e);
+    ActixHttpResponse::build(status).json(body)
+}
+
+fn error_outcome(e: &anyhow::Error) -> Outcome {
+    let (status, body) = HttpResponse::from_error(e);
+    Outcome::Response(status, body)
+}
+
+#[utoipa::path(
+    tag = "health",
+    security(()),
+    responses((status = 200, description = "The server is up", body = String, content_type = "text/plain"))
+)]
 #[get("/healthz")]
 pub async fn healthz() -> Result<ActixHttpResponse, Error> {
     Ok(ActixHttpResponse::Ok().body("Server up and running"))
 }
 
-#[put("/{org_id}/reports/{name}/send")]
-pub async fn send_report(
-    report: web::Json<Report>,
-    path: web::Path<(String, String)>,
-    req: HttpRequest,
-) -> Result<ActixHttpResponse, Error> {
-    let report = report.into_inner();
-    let (org_id, report_name) = path.into_inner();
-    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
-    let timezone = match query.get("timezone") {
-        Some(v) => v,
-        None => "Europe/London",
+/// Last result of the checks of Chromium, O2 and the SMTP servers, with the status of each
+/// component. The errors of the components are only returned with an api token.
+#[utoipa::path(
+    tag = "health",
+    security(()),
+    responses(
+        (status = 200, description = "All components work", body = Readiness),
+        (status = 503, description = "A component does not work or was not checked yet", body = Readiness),
+    )
+)]
+#[get("/readyz")]
+pub async fn readyz(req: HttpRequest) -> Result<ActixHttpResponse, Error> {
+    let readiness = readiness::check();
+    // The probe is open, the errors are only shown to api clients
+    let readiness = if auth::is_authorized(req.headers()) {
+        readiness
+    } else {
+        readiness.without_errors()
     };
+    if readiness.ready {
+        Ok(ActixHttpResponse::Ok().json(readiness))
+    } else {
+        Ok(ActixHttpResponse::ServiceUnavailable().json(readiness))
+    }
+}
 
+/// OpenAPI document of the api, generated from the routes and their payload types
+#[utoipa::path(
+    tag = "health",
+    security(()),
+    responses((status = 200, description = "OpenAPI 3 document", body = Object))
+)]
+#[get("/openapi.json")]
+pub async fn openapi_spec() -> Result<ActixHttpResponse, Error> {
+    Ok(ActixHttpResponse::Ok()
+        .content_type("application/json")
+        .body(crate::openapi::SPEC.as_str()))
+}
+
+/// Prometheus metrics, served on `/metrics` outside of `/api` with its own token check
+#[get("")]
+pub async fn metrics() -> Result<ActixHttpResponse, Error> {
+    match crate::metrics::gather() {
+        Ok(body) => Ok(ActixHttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body)),
+        Err(e) => Ok(ActixHttpResponse::InternalServerError()
+            .json(HttpResponse::internal_server_error(e.to_string()))),
+    }
+}
+
+/// Checks the report and returns its dashboard and the report type, or the error status and
+/// message. Reports without recipients only cache the dashboard data.
+pub(crate) fn check_report(
+    report: &Report,
+    org_id: &str,
+    report_name: &str,
+) -> Result<(ReportDashboard, ReportType), (StatusCode, String)> {
     // ensure a dashboard was provided and if not raise a helpful error with a 400
-    if report.dashboards.len() == 0 {
+    if report.dashboards.is_empty() {
         log::error!("At least 1 dashboard must be provided when sending a report");
-        return Ok(
-            ActixHttpResponse::build(StatusCode::BAD_REQUEST).json(HttpResponse::new(
-                "At least 1 dashboard must be provided when sending a report".to_string(),
-                StatusCode::BAD_REQUEST.into(),
-            )),
-        );
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "At least 1 dashboard must be provided when sending a report".to_string(),
+        ));
     }
 
     // Since only 1 dashboard is supported currently per report, grab the first one
@@ -105,75 +230,937 @@ pub async fn send_report(
     let report_type = if report.email_details.recipients.is_empty() {
         ReportType::Cache
     } else {
-        dashboard_for_report.report_type.clone()
+        dashboard_for_report.report_type
     };
 
     // If inline attachment was desired but not a PDF, raise an exception since most mail servers
     // will only let you embed simple images.
     if report_type == ReportType::PDF && dashboard_for_report.email_attachment_type == Inline {
         log::warn!("Inline PDF attachments are not allowed. Report: {org_id}/{report_name}");
-        return Ok(
-            ActixHttpResponse::build(StatusCode::CONFLICT).json(HttpResponse::new(
-                "Most email servers do not support inline PDF attachments, \
-                for inline attachments please use a PNG."
-                    .to_string(),
-                StatusCode::CONFLICT.into(),
-            )),
+        return Err((
+            StatusCode::CONFLICT,
+            "Most email servers do not support inline PDF attachments, \
+            for inline attachments please use a PNG."
+                .to_string(),
+        ));
+    }
+    if let Some(url) = &report.callback_url {
+        if let Err(e) = webhook::validate_url(url) {
+            return Err((StatusCode::BAD_REQUEST, e.to_string()));
+        }
+    }
+    Ok((dashboard_for_report, report_type))
+}
+
+/// [`check_report`] returning the error response
+#[allow(clippy::result_large_err)]
+fn validate_report(
+    report: &Report,
+    org_id: &str,
+    report_name: &str,
+) -> Result<(ReportDashboard, ReportType), ActixHttpResponse> {
+    check_report(report, org_id, report_name).map_err(|(status, msg)| {
+        ActixHttpResponse::build(status).json(HttpResponse::new(msg, status.into()))
+    })
+}
+
+/// Resolves the SMTP profile of the report, or returns the error response
+#[allow(clippy::result_large_err)]
+fn smtp_config_for(
+    report: &Report,
+    org_id: &str,
+    report_name: &str,
+) -> Result<SmtpConfig, ActixHttpResponse> {
+    SmtpConfig::for_report(org_id, report.email_details.smtp_profile.as_deref()).map_err(|e| {
+        log::error!("Error resolving the SMTP profile for report {org_id}/{report_name}: {e}");
+        ActixHttpResponse::BadRequest().json(HttpResponse::new(
+            e.to_string(),
+            StatusCode::BAD_REQUEST.into(),
+        ))
+    })
+}
+
+/// Takes a place in the render queue, or returns a 503 when the queue is full
+#[allow(clippy::result_large_err)]
+fn enqueue_render(org_id: &str) -> Result<Ticket, ActixHttpResponse> {
+    render_queue::enqueue(org_id).map_err(|e| {
+        ActixHttpResponse::ServiceUnavailable()
+            .insert_header((RETRY_AFTER, e.retry_after_secs.to_string()))
+            .json(HttpResponse::new(
+                e.to_string(),
+                StatusCode::SERVICE_UNAVAILABLE.into(),
+            ))
+    })
+}
+
+/// Counts the run against the limits of the org, or returns a 429 when it exceeds one
+#[allow(clippy::result_large_err)]
+fn admit(org_id: &str, recipients: usize) -> Result<Admission, ActixHttpResponse> {
+    limits::admit(org_id, recipients).map_err(|rejection| {
+        ActixHttpResponse::TooManyRequests()
+            .insert_header((RETRY_AFTER, rejection.retry_after_secs.to_string()))
+            .json(HttpResponse::from_report_error(&rejection.into()))
+    })
+}
+
+/// Renders the dashboard once the ticket gets a render slot and builds the report email
+#[allow(clippy::too_many_arguments)]
+async fn render_with_slot(
+    ticket: Ticket,
+    report: &Report,
+    dashboard_for_report: &ReportDashboard,
+    report_type: ReportType,
+    org_id: &str,
+    report_name: &str,
+    timezone: &str,
+    smtp_config: &SmtpConfig,
+) -> Result<RenderedReport, anyhow::Error> {
+    let _permit = ticket
+        .wait()
+        .instrument(tracing::info_span!("queue_wait"))
+        .await;
+    crate::render_report(
+        report,
+        dashboard_for_report,
+        report_type,
+        org_id,
+        report_name,
+        timezone,
+        smtp_config,
+    )
+    .await
+    .inspect_err(|e| log::error!("Error rendering report {org_id}/{report_name}: {e}"))
+}
+
+/// Validates the report and takes a place in the render queue, or returns the error response
+#[allow(clippy::result_large_err)]
+fn prepare_send(
+    report: Report,
+    org_id: &str,
+    report_name: &str,
+    timezone: &str,
+) -> Result<JobRequest, ActixHttpResponse> {
+    let (dashboard_for_report, report_type) = validate_report(&report, org_id, report_name)?;
+    tracing::Span::current().record("dashboard", dashboard_for_report.dashboard.as_str());
+    let smtp_config = if report_type == ReportType::Cache {
+        None
+    } else {
+        Some(smtp_config_for(&report, org_id, report_name)?)
+    };
+    let ticket = enqueue_render(org_id)?;
+    let admission = admit(org_id, report.email_details.recipients.len())?;
+    Ok(JobRequest {
+        org_id: org_id.to_string(),
+        report_name: report_name.to_string(),
+        report,
+        dashboard: dashboard_for_report,
+        report_type,
+        timezone: timezone.to_string(),
+        smtp_config,
+        ticket,
+        admission,
+    })
+}
+
+/// Idempotency key of a send request, from the `Idempotency-Key` header or derived from the
+/// `scheduled_at` query parameter. `None` when the request has neither or keys are disabled.
+#[allow(clippy::result_large_err)]
+fn idempotency_key(
+    req: &HttpRequest,
+    query: &HashMap<String, String>,
+    org_id: &str,
+    report_name: &str,
+) -> Result<Option<String>, ActixHttpResponse> {
+    if CONFIG.idempotency.window_secs == 0 {
+        return Ok(None);
+    }
+    let bad_request = |msg: &str| {
+        ActixHttpResponse::BadRequest().json(HttpResponse::new(
+            msg.to_string(),
+            StatusCode::BAD_REQUEST.into(),
+        ))
+    };
+    if let Some(key) = req.headers().get(IDEMPOTENCY_KEY) {
+        let key = key.to_str().unwrap_or_default().trim();
+        if key.is_empty() || key.len() > 255 {
+            return Err(bad_request(
+                "Idempotency-Key must be a text of 1 to 255 characters",
+            ));
+        }
+        return Ok(Some(format!("{org_id}/{report_name}/key:{key}")));
+    }
+    match query.get("scheduled_at").map(|t| t.parse::<i64>()) {
+        Some(Ok(scheduled_at)) => Ok(Some(format!(
+            "{org_id}/{report_name}/scheduled:{scheduled_at}"
+        ))),
+        Some(Err(_)) => Err(bad_request(
+            "scheduled_at must be a timestamp in microseconds",
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Response of a send request, repeated requests with the same idempotency key are marked
+/// with the `Idempotent-Replayed` header
+fn outcome_response(outcome: Outcome, replayed: bool) -> ActixHttpResponse {
+    let mut resp = match outcome {
+        Outcome::Response(status, body) => ActixHttpResponse::build(status).json(body),
+        // Returns the current state of the job
+        Outcome::Job(job) => ActixHttpResponse::Accepted().json(jobs::get(&job.id).unwrap_or(job)),
+    };
+    if replayed {
+        resp.headers_mut().insert(
+            HeaderName::from_static("idempotent-replayed"),
+            HeaderValue::from_static("true"),
         );
     }
+    resp
+}
+
+/// Sends the report. With an idempotency key, a repeated request joins the run of the first
+/// request or returns its stored result for `ZO_IDEMPOTENCY_WINDOW_SECS`, and the run goes on
+/// when the client disconnects.
+#[utoipa::path(
+    tag = "reports",
+    params(
+        ("org_id" = String, Path, description = "Organization of the report"),
+        ("name" = String, Path, description = "Name of the report"),
+        ("timezone" = Option<String>, Query, description = "Timezone of the dashboard, defaults to Europe/London"),
+        ("async" = Option<bool>, Query, description = "Return a job right away instead of waiting for the run"),
+        ("scheduled_at" = Option<i64>, Query, description = "Scheduled time of the report in microseconds, used as idempotency key"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Repeated requests with the same key get the result of the first run"),
+    ),
+    request_body = Report,
+    responses(
+        (status = 200, description = "Report sent to all recipients or dashboard data cached", body = HttpResponse),
+        (status = 202, description = "Job of an async run", body = Job),
+        (status = 207, description = "Report sent to some recipients", body = HttpResponse),
+        (status = 400, description = "Invalid report", body = HttpResponse),
+        (status = 409, description = "Inline pdf attachment", body = HttpResponse),
+        (status = 422, description = "Recipients rejected or idempotency key reused for another request", body = HttpResponse),
+        (status = 429, description = "Org limit exceeded", body = HttpResponse),
+        (status = 503, description = "Render queue full or SMTP server unavailable", body = HttpResponse),
+        (status = "5XX", description = "Report run failed, see the error code", body = HttpResponse),
+    )
+)]
+#[put("/{org_id}/reports/{name}/send")]
+#[tracing::instrument(
+    name = "send_report",
+    skip_all,
+    fields(org_id = %path.0, report_name = %path.1, dashboard = tracing::field::Empty)
+)]
+pub async fn send_report(
+    report: web::Json<Report>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<ActixHttpResponse, Error> {
+    let report = report.into_inner();
+    let (org_id, report_name) = path.into_inner();
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let timezone = match query.get("timezone") {
+        Some(v) => v,
+        None => DEFAULT_TIMEZONE,
+    };
+    // Async mode returns the job right away, its status is polled on /jobs/{id}
+    let is_async = query.get("async").is_some_and(|v| v == "true");
+
+    let key = match idempotency_key(&req, &query, &org_id, &report_name) {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            let request = match prepare_send(report, &org_id, &report_name, timezone) {
+                Ok(request) => request,
+                Err(resp) => return Ok(resp),
+            };
+            return Ok(outcome_response(send(request, is_async).await, false));
+        }
+        Err(resp) => return Ok(resp),
+    };
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(&report)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    (timezone, is_async).hash(&mut hasher);
+    let fingerprint = hasher.finish();
+
+    loop {
+        match idempotency::claim(&key, fingerprint) {
+            Claim::New(pending) => {
+                let request = match prepare_send(report, &org_id, &report_name, timezone) {
+                    Ok(request) => request,
+                    Err(resp) => return Ok(resp),
+                };
+                let run = tokio::task::spawn(
+                    async move {
+                        let outcome = send(request, is_async).await;
+                        pending.complete(outcome.clone());
+                        outcome
+                    }
+                    .in_current_span(),
+                );
+                return match run.await {
+                    Ok(outcome) => Ok(outcome_response(outcome, false)),
+                    Err(e) => Ok(ActixHttpResponse::InternalServerError()
+                        .json(HttpResponse::internal_server_error(e.to_string()))),
+                };
+            }
+            Claim::Existing(rx) => {
+                log::info!("[{org_id}/{report_name}] repeated send request, joining the first run");
+                if let Some(outcome) = idempotency::wait(rx).await {
+                    return Ok(outcome_response(outcome, true));
+                }
+            }
+            Claim::Mismatch => {
+                return Ok(
+                    ActixHttpResponse::UnprocessableEntity().json(HttpResponse::new(
+                        "The idempotency key was already used for a different request".to_string(),
+                        StatusCode::UNPROCESSABLE_ENTITY.into(),
+                    )),
+                );
+            }
+        }
+    }
+}
+
+/// Runs the report, or submits it as a job in async mode
+async fn send(request: JobRequest, is_async: bool) -> Outcome {
+    if is_async {
+        return Outcome::Job(jobs::submit(request));
+    }
+    let JobRequest {
+        org_id,
+        report_name,
+        report,
+        dashboard: dashboard_for_report,
+        report_type,
+        timezone,
+        smtp_config,
+        ticket,
+        admission,
+    } = request;
+    let timezone = timezone.as_str();
+
+    let mut run = RunStatus::new(&org_id, &report_name);
+    let Some(smtp_config) = smtp_config else {
+        let started = std::time::Instant::now();
+        let permit = ticket.wait().await;
+        let generated = crate::generate_report(
+            &dashboard_for_report,
+            &org_id,
+            &report_name,
+            &CONFIG.auth.user_email,
+            &CONFIG.auth.user_password,
+            &CONFIG.common.o2_web_uri,
+            timezone,
+            report_type,
+            report.email_details.image_preview,
+        )
+        .await;
+        drop(permit);
+        run.render_duration_ms = Some(started.elapsed().as_millis() as u64);
+        return match generated {
+            Ok(generated) => {
+                log::info!("Dashboard data cached by report {report_name}");
+                run.dashboard_url = Some(generated.dashboard_url);
+                run.succeed();
+                webhook::notify(report.callback_url, run);
+                Outcome::Response(
+                    StatusCode::OK,
+                    HttpResponse::success(format!("dashboard data cached by report {report_name}")),
+                )
+            }
+            Err(e) => {
+                log::error!("Error generating pdf for report {org_id}/{report_name}: {e}");
+                limits::refund_render(&admission);
+                run.fail(&e);
+                webhook::notify(report.callback_url, run);
+                error_outcome(&e)
+            }
+        };
+    };
+
+    let rendered = match render_with_slot(
+        ticket,
+        &report,
+        &dashboard_for_report,
+        report_type,
+        &org_id,
+        &report_name,
+        timezone,
+        &smtp_config,
+    )
+    .await
+    {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            limits::refund_render(&admission);
+            run.fail(&e);
+            webhook::notify(report.callback_url, run);
+            return error_outcome(&e);
+        }
+    };
+    run.artifact_size = Some(rendered.attachment.len());
+    run.render_duration_ms = Some(rendered.render_duration.as_millis() as u64);
+    run.dashboard_url = Some(rendered.dashboard_url);
+    if let Err(rejection) = limits::add_attachment(&org_id, rendered.attachment.len()) {
+        limits::refund_render(&admission);
+        let e = ReportError::from(rejection).into();
+        run.fail(&e);
+        webhook::notify(report.callback_url, run);
+        return error_outcome(&e);
+    }
+
+    match crate::send_email(&org_id, &report_name, rendered.email, &smtp_config).await {
+        Ok(sent_email) => {
+            let results = sent_email.recipients;
+            run.sent(&results);
+            webhook::notify(report.callback_url, run);
+            let count = |status| results.iter().filter(|r| r.status == status).count();
+            let (sent, failed) = (count(DeliveryStatus::Sent), count(DeliveryStatus::Failed));
+            let (status, body) = if sent == results.len() {
+                (
+                    StatusCode::OK,
+                    HttpResponse::success("report sent to emails successfully".to_string()),
+                )
+            } else if failed < results.len() {
+                log::warn!(
+                    "Report {org_id}/{report_name} was sent to {sent}/{} recipients",
+                    results.len()
+                );
+                let msg = format!(
+                    "report sent to {sent} of {} recipients, {} queued for retry",
+                    results.len(),
+                    count(DeliveryStatus::Queued)
+                );
+                (
+                    StatusCode::MULTI_STATUS,
+                    HttpResponse::new(msg, StatusCode::MULTI_STATUS.into()),
+                )
+            } else {
+                log::error!("Report {org_id}/{report_name} could not be sent to any recipient");
+                let error = ReportError::from_recipients(&results);
+                (error.status(), HttpResponse::from_report_error(&error))
+            };
+            Outcome::Response(
+                status,
+                body.with_recipients(results)
+                    .with_eml_path(sent_email.eml_path),
+            )
+        }
+        Err(e) => {
+            log::error!("Error sending emails to recepients: {e}");
+            run.fail(&e);
+            webhook::notify(report.callback_url, run);
+            error_outcome(&e)
+        }
+    }
+}
+
+/// Renders the report email like the send endpoint but returns it instead of sending it.
+/// Returns the raw message with `?format=eml`, else a JSON with the rendered parts.
+#[utoipa::path(
+    tag = "reports",
+    params(
+        ("org_id" = String, Path, description = "Organization of the report"),
+        ("name" = String, Path, description = "Name of the report"),
+        ("timezone" = Option<String>, Query, description = "Timezone of the dashboard, defaults to Europe/London"),
+        ("format" = Option<String>, Query, description = "json, the default, or eml for the raw message"),
+    ),
+    request_body = Report,
+    responses(
+        (status = 200, description = "Rendered email", body = EmailPreview),
+        (status = 400, description = "Invalid report", body = HttpResponse),
+        (status = 429, description = "Org limit exceeded", body = HttpResponse),
+        (status = 503, description = "Render queue full", body = HttpResponse),
+    )
+)]
+#[put("/{org_id}/reports/{name}/preview")]
+pub async fn preview_report(
+    report: web::Json<Report>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<ActixHttpResponse, Error> {
+    let report = report.into_inner();
+    let (org_id, report_name) = path.into_inner();
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let timezone = match query.get("timezone") {
+        Some(v) => v,
+        None => DEFAULT_TIMEZONE,
+    };
+    let as_eml = match query.get("format").map(|f| f.as_str()) {
+        None | Some("json") => false,
+        Some("eml") => true,
+        Some(other) => {
+            return Ok(ActixHttpResponse::BadRequest().json(HttpResponse::new(
+                format!("Unknown preview format {other}, expected json or eml"),
+                StatusCode::BAD_REQUEST.into(),
+            )));
+        }
+    };
 
-    let image_preview = report.email_details.image_preview;
+    let (dashboard_for_report, report_type) = match validate_report(&report, &org_id, &report_name)
+    {
+        Ok(res) => res,
+        Err(resp) => return Ok(resp),
+    };
+    if report_type == ReportType::Cache {
+        return Ok(ActixHttpResponse::BadRequest().json(HttpResponse::new(
+            "At least 1 recipient must be provided to preview a report email".to_string(),
+            StatusCode::BAD_REQUEST.into(),
+        )));
+    }
+    let ticket = match enqueue_render(&org_id) {
+        Ok(ticket) => ticket,
+        Err(resp) => return Ok(resp),
+    };
 
-    let (attachment_data, email_dashboard_url, preview_image) = match crate::generate_report(
+    let smtp_config = match smtp_config_for(&report, &org_id, &report_name) {
+        Ok(config) => config,
+        Err(resp) => return Ok(resp),
+    };
+    // Previews are rendered like reports but not emailed
+    let admission = match admit(&org_id, 0) {
+        Ok(admission) => admission,
+        Err(resp) => return Ok(resp),
+    };
+    let email = match render_with_slot(
+        ticket,
+        &report,
         &dashboard_for_report,
+        report_type,
         &org_id,
         &report_name,
+        timezone,
+        &smtp_config,
+    )
+    .await
+    {
+        Ok(rendered) => rendered.email,
+        Err(e) => {
+            limits::refund_render(&admission);
+            return Ok(error_response(&e));
+        }
+    };
+    let (Some(message), Some(preview)) = (email.message, email.preview) else {
+        return Ok(ActixHttpResponse::BadRequest().json(
+            HttpResponse::new(
+                "None of the recipients has a valid email address".to_string(),
+                StatusCode::BAD_REQUEST.into(),
+            )
+            .with_recipients(email.rejected),
+        ));
+    };
+
+    log::info!("Preview rendered for report {org_id}/{report_name}");
+    if as_eml {
+        Ok(ActixHttpResponse::Ok()
+            .content_type("message/rfc822")
+            .insert_header(ContentDisposition::attachment(format!("{report_name}.eml")))
+            .body(message.formatted()))
+    } else {
+        Ok(ActixHttpResponse::Ok().json(preview))
+    }
+}
+
+/// Checks the report like the send endpoint would, without rendering or sending it, and
+/// returns all field-level errors. With `?reachability=true` it also logs in to O2 and checks
+/// that the folder, the dashboard and its tabs exist.
+#[utoipa::path(
+    tag = "reports",
+    params(
+        ("org_id" = String, Path, description = "Organization of the report"),
+        ("name" = String, Path, description = "Name of the report"),
+        ("timezone" = Option<String>, Query, description = "Timezone of the dashboard, defaults to Europe/London"),
+        ("reachability" = Option<bool>, Query, description = "Also check that the folder, dashboard and tabs exist in O2"),
+    ),
+    request_body = Report,
+    responses((status = 200, description = "Errors found in the report", body = Validation))
+)]
+#[put("/{org_id}/reports/{name}/validate")]
+pub async fn validate_report_definition(
+    body: web::Json<serde_json::Value>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<ActixHttpResponse, Error> {
+    let (org_id, report_name) = path.into_inner();
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let report = match serde_json::from_value::<Report>(body.into_inner()) {
+        Ok(report) => report,
+        Err(e) => {
+            return Ok(
+                ActixHttpResponse::Ok().json(Validation::new(vec![FieldError {
+                    field: "body".to_string(),
+                    message: e.to_string(),
+                }])),
+            );
+        }
+    };
+
+    let mut errors = validation::check(&report, &org_id, query.get("timezone").map(|t| t.as_str()));
+    if query.get("reachability").is_some_and(|v| v == "true") {
+        errors.extend(validation::check_reachability(&report, &org_id).await);
+    }
+    log::info!(
+        "Report {org_id}/{report_name} validated with {} errors",
+        errors.len()
+    );
+    Ok(ActixHttpResponse::Ok().json(Validation::new(errors)))
+}
+
+/// Body of the download endpoint, a [`ReportDashboard`] with optional download settings
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DownloadRequest {
+    #[serde(flatten)]
+    pub dashboard: ReportDashboard,
+    /// O2 web url, one of `ZO_O2_ALLOWED_URLS`, defaults to `ZO_O2_APP_URL`
+    #[serde(default)]
+    pub dashb_url: Option<String>,
+    /// Name of the downloaded file without extension, defaults to the dashboard id
+    #[serde(default)]
+    pub filename: Option<String>,
+}
+
+/// Renders the dashboard and returns the report file
+async fn download(
+    org_id: &str,
+    request: DownloadRequest,
+    timezone: &str,
+) -> Result<ActixHttpResponse, Error> {
+    let dashboard = request.dashboard;
+    let report_type = dashboard.report_type;
+    let (Some(content_type), Some(extension)) =
+        (report_type.content_type(), report_type.file_extension())
+    else {
+        return Ok(ActixHttpResponse::BadRequest().json(HttpResponse::new(
+            "Report type must be pdf, png or csv".to_string(),
+            StatusCode::BAD_REQUEST.into(),
+        )));
+    };
+    let report_name = format!("download-{}", dashboard.dashboard);
+    let web_url = request
+        .dashb_url
+        .unwrap_or_else(|| CONFIG.common.o2_web_uri.clone());
+    if let Err(e) = config::check_o2_url(&web_url) {
+        return Ok(ActixHttpResponse::BadRequest().json(HttpResponse::new(
+            e.to_string(),
+            StatusCode::BAD_REQUEST.into(),
+        )));
+    }
+    let ticket = match enqueue_render(org_id) {
+        Ok(ticket) => ticket,
+        Err(resp) => return Ok(resp),
+    };
+    let admission = match admit(org_id, 0) {
+        Ok(admission) => admission,
+        Err(resp) => return Ok(resp),
+    };
+
+    let permit = ticket.wait().await;
+    let generated = match crate::generate_report(
+        &dashboard,
+        org_id,
+        &report_name,
         &CONFIG.auth.user_email,
         &CONFIG.auth.user_password,
-        &report.email_details.dashb_url,
+        &web_url,
         timezone,
-        report_type.clone(),
-        image_preview,
+        report_type,
+        false,
     )
     .await
     {
         Ok(res) => res,
         Err(e) => {
-            log::error!("Error generating pdf for report {org_id}/{report_name}: {e}");
-            return Ok(ActixHttpResponse::InternalServerError()
-                .json(HttpResponse::internal_server_error(e.to_string())));
+            log::error!("Error generating {extension} for {org_id}/{report_name}: {e}");
+            limits::refund_render(&admission);
+            return Ok(error_response(&e));
         }
     };
+    drop(permit);
 
-    if report_type == ReportType::Cache {
-        log::info!("Dashboard data cached by report {report_name}");
-        return Ok(ActixHttpResponse::Ok().json(HttpResponse::success(format!(
-            "dashboard data cached by report {report_name}"
-        ))));
-    }
-
-    match crate::send_email(
-        &attachment_data,
-        report.dashboards[0].report_type.clone(),
-        report.dashboards[0].email_attachment_type.clone(),
-        crate::EmailDetails {
-            dashb_url: email_dashboard_url,
-            ..report.email_details
+    let filename = request.filename.as_deref().unwrap_or(&dashboard.dashboard);
+    let filename =
+        format!("{}.{extension}", crate::sanitize_filename(filename.trim())).replace(" ", "_");
+    log::info!(
+        "Dashboard {org_id}/{} downloaded as {filename}",
+        dashboard.dashboard
+    );
+    Ok(ActixHttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition::attachment(filename))
+        .body(generated.attachment))
+}
+
+/// Renders the dashboard in the body and returns the pdf, png or csv zip
+#[utoipa::path(
+    tag = "dashboards",
+    params(
+        ("org_id" = String, Path, description = "Organization of the dashboard"),
+        ("timezone" = Option<String>, Query, description = "Timezone of the dashboard, defaults to Europe/London"),
+    ),
+    request_body = DownloadRequest,
+    responses(
+        (status = 200, description = "Report file, a zip of csv files for csv reports", content(("application/pdf"), ("image/png"), ("application/zip"))),
+        (status = 400, description = "Invalid request", body = HttpResponse),
+        (status = 429, description = "Org limit exceeded", body = HttpResponse),
+        (status = 503, description = "Render queue full", body = HttpResponse),
+    )
+)]
+#[post("/{org_id}/dashboards/download")]
+pub async fn download_dashboard(
+    request: web::Json<DownloadRequest>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<ActixHttpResponse, Error> {
+    let org_id = path.into_inner();
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let timezone = match query.get("timezone") {
+        Some(v) => v,
+        None => DEFAULT_TIMEZONE,
+    };
+    download(&org_id, request.into_inner(), timezone).await
+}
+
+/// Renders a dashboard described by query parameters: `folder`, `tabs` (comma separated),
+/// `type`, `period` or `from` and `to` in microseconds, `timezone`, `dashb_url`, `filename`
+/// and `var.<key>` for the dashboard variables
+#[utoipa::path(
+    tag = "dashboards",
+    params(
+        ("org_id" = String, Path, description = "Organization of the dashboard"),
+        ("dashboard" = String, Path, description = "Dashboard id"),
+        ("folder" = Option<String>, Query, description = "Folder id, defaults to default"),
+        ("tabs" = Option<String>, Query, description = "Comma separated tab ids, defaults to default"),
+        ("type" = Option<ReportType>, Query, description = "Report type, defaults to pdf"),
+        ("period" = Option<String>, Query, description = "Relative timerange, defaults to 1w"),
+        ("from" = Option<i64>, Query, description = "Start of an absolute timerange in microseconds"),
+        ("to" = Option<i64>, Query, description = "End of an absolute timerange in microseconds"),
+        ("timezone" = Option<String>, Query, description = "Timezone of the dashboard, defaults to Europe/London"),
+        ("dashb_url" = Option<String>, Query, description = "O2 web url, one of ZO_O2_ALLOWED_URLS"),
+        ("filename" = Option<String>, Query, description = "Name of the file without extension"),
+    ),
+    responses(
+        (status = 200, description = "Report file, a zip of csv files for csv reports", content(("application/pdf"), ("image/png"), ("application/zip"))),
+        (status = 400, description = "Invalid request", body = HttpResponse),
+        (status = 429, description = "Org limit exceeded", body = HttpResponse),
+        (status = 503, description = "Render queue full", body = HttpResponse),
+    )
+)]
+#[get("/{org_id}/dashboards/{dashboard}/download")]
+pub async fn download_dashboard_by_query(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<ActixHttpResponse, Error> {
+    let (org_id, dashboard_id) = path.into_inner();
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let timezone = match query.get("timezone") {
+        Some(v) => v,
+        None => DEFAULT_TIMEZONE,
+    };
+
+    let report_type = match query.get("type") {
+        Some(t) => match serde_json::from_value::<ReportType>(serde_json::Value::String(t.clone()))
+        {
+            Ok(t) => t,
+            Err(_) => {
+                return Ok(ActixHttpResponse::BadRequest().json(HttpResponse::new(
+                    format!("Unknown report type {t}, expected pdf, png or csv"),
+                    StatusCode::BAD_REQUEST.into(),
+                )));
+            }
         },
-        crate::SmtpConfig {
-            from_email: CONFIG.smtp.smtp_from_email.to_string(),
-            reply_to: CONFIG.smtp.smtp_reply_to.to_string(),
-            client: &SMTP_CLIENT,
+        None => ReportType::PDF,
+    };
+    let timerange = match (query.get("from"), query.get("to")) {
+        (Some(from), Some(to)) => match (from.parse(), to.parse()) {
+            (Ok(from), Ok(to)) => ReportTimerange {
+                range_type: ReportTimerangeType::Absolute,
+                period: String::new(),
+                from,
+                to,
+            },
+            _ => {
+                return Ok(ActixHttpResponse::BadRequest().json(HttpResponse::new(
+                    "from and to must be timestamps in microseconds".to_string(),
+                    StatusCode::BAD_REQUEST.into(),
+                )));
+            }
         },
-        preview_image,
+        _ => ReportTimerange {
+            period: query.get("period").cloned().unwrap_or("1w".to_string()),
+            ..Default::default()
+        },
+    };
+    let mut variables: Vec<ReportDashboardVariable> = query
+        .iter()
+        .filter_map(|(k, v)| {
+            k.strip_prefix("var.").map(|key| ReportDashboardVariable {
+                key: key.to_string(),
+                value: v.clone(),
+                id: None,
+            })
+        })
+        .collect();
+    variables.sort_by(|a, b| a.key.cmp(&b.key));
+
+    let request = DownloadRequest {
+        dashboard: ReportDashboard {
+            dashboard: dashboard_id,
+            folder: query
+                .get("folder")
+                .cloned()
+                .unwrap_or("default".to_string()),
+            tabs: query
+                .get("tabs")
+                .map(|t| t.split(',').map(|t| t.trim().to_string()).collect())
+                .unwrap_or(vec!["default".to_string()]),
+            variables,
+            timerange,
+            report_type,
+            email_attachment_type: EmailAttachmentType::Standard,
+            attachment_dimensions: Default::default(),
+        },
+        dashb_url: query.get("dashb_url").cloned(),
+        filename: query.get("filename").cloned(),
+    };
+    download(&org_id, request, timezone).await
+}
+
+#[utoipa::path(
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "The job", body = Job),
+        (status = 404, description = "Unknown job", body = HttpResponse),
     )
-    .await
+)]
+#[get("/jobs/{id}")]
+pub async fn get_job(path: web::Path<String>) -> Result<ActixHttpResponse, Error> {
+    let id = path.into_inner();
+    match jobs::get(&id) {
+        Some(job) => Ok(ActixHttpResponse::Ok().json(job)),
+        None => Ok(ActixHttpResponse::NotFound().json(HttpResponse::new(
+            format!("Job {id} not found"),
+            StatusCode::NOT_FOUND.into(),
+        ))),
+    }
+}
+
+/// Returns the rendered report file of the job
+#[utoipa::path(
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "Rendered report file", content(("application/pdf"), ("image/png"), ("application/zip"))),
+        (status = 404, description = "Unknown job or not rendered yet", body = HttpResponse),
+    )
+)]
+#[get("/jobs/{id}/artifact")]
+pub async fn get_job_artifact(path: web::Path<String>) -> Result<ActixHttpResponse, Error> {
+    let id = path.into_inner();
+    match jobs::artifact(&id).await {
+        Ok(Some((artifact, data))) => Ok(ActixHttpResponse::Ok()
+            .content_type(artifact.content_type)
+            .insert_header(ContentDisposition::attachment(artifact.filename))
+            .body(data)),
+        Ok(None) => Ok(ActixHttpResponse::NotFound().json(HttpResponse::new(
+            format!("Job {id} has no artifact"),
+            StatusCode::NOT_FOUND.into(),
+        ))),
+        Err(e) => {
+            log::error!("Error reading the artifact of job {id}: {e}");
+            Ok(ActixHttpResponse::InternalServerError()
+                .json(HttpResponse::internal_server_error(e.to_string())))
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "outbox",
+    params(("state" = Option<OutboxState>, Query, description = "Only list entries in this state")),
+    responses(
+        (status = 200, description = "Outbox entries", body = Vec<OutboxEntry>),
+        (status = 400, description = "Unknown state", body = HttpResponse),
+    )
+)]
+#[get("/outbox")]
+pub async fn list_outbox(req: HttpRequest) -> Result<ActixHttpResponse, Error> {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let state = match query
+        .get("state")
+        .map(|s| s.parse::<OutboxState>())
+        .transpose()
     {
-        Ok(_) => Ok(ActixHttpResponse::Ok().json(HttpResponse::success(
-            "report sent to emails successfully".to_string(),
+        Ok(state) => state,
+        Err(e) => {
+            return Ok(ActixHttpResponse::BadRequest().json(HttpResponse::new(
+                e.to_string(),
+                StatusCode::BAD_REQUEST.into(),
+            )));
+        }
+    };
+    match outbox::list(state).await {
+        Ok(entries) => Ok(ActixHttpResponse::Ok().json(entries)),
+        Err(e) => {
+            log::error!("Error listing the outbox: {e}");
+            Ok(ActixHttpResponse::InternalServerError()
+                .json(HttpResponse::internal_server_error(e.to_string())))
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "outbox",
+    params(("id" = String, Path, description = "Outbox entry id")),
+    responses(
+        (status = 200, description = "The outbox entry", body = OutboxEntry),
+        (status = 404, description = "Unknown outbox entry", body = HttpResponse),
+    )
+)]
+#[get("/outbox/{id}")]
+pub async fn get_outbox_entry(path: web::Path<String>) -> Result<ActixHttpResponse, Error> {
+    let id = path.into_inner();
+    match outbox::get(&id).await {
+        Ok(Some(entry)) => Ok(ActixHttpResponse::Ok().json(entry)),
+        Ok(None) => Ok(ActixHttpResponse::NotFound().json(HttpResponse::new(
+            format!("outbox entry {id} not found"),
+            StatusCode::NOT_FOUND.into(),
+        ))),
+        Err(e) => Ok(ActixHttpResponse::BadRequest().json(HttpResponse::new(
+            e.to_string(),
+            StatusCode::BAD_REQUEST.into(),
+        ))),
+    }
+}
+
+/// Delivers an outbox entry again to the recipients which did not receive it,
+/// or to all recipients with `?all=true`
+#[utoipa::path(
+    tag = "outbox",
+    params(
+        ("id" = String, Path, description = "Outbox entry id"),
+        ("all" = Option<bool>, Query, description = "Deliver to all recipients"),
+    ),
+    responses(
+        (status = 200, description = "The outbox entry after the delivery", body = OutboxEntry),
+        (status = 404, description = "Unknown outbox entry", body = HttpResponse),
+    )
+)]
+#[post("/outbox/{id}/replay")]
+pub async fn replay_outbox_entry(
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<ActixHttpResponse, Error> {
+    let id = path.into_inner();
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let all = query.get("all").is_some_and(|v| v == "true");
+    match outbox::replay(&id, all).await {
+        Ok(Some(entry)) => Ok(ActixHttpResponse::Ok().json(entry)),
+        Ok(None) => Ok(ActixHttpResponse::NotFound().json(HttpResponse::new(
+            format!("outbox entry {id} not found"),
+            StatusCode::NOT_FOUND.into(),
         ))),
         Err(e) => {
-            log::error!("Error sending emails to recepients: {e}");
+            log::error!("Error replaying outbox entry {id}: {e}");
             Ok(ActixHttpResponse::InternalServerError()
                 .json(HttpResponse::internal_server_error(e.to_string())))
         }
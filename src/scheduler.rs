@@ -0,0 +1,462 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{
+    config::CONFIG,
+    jobs::{self, JobRequest},
+    limits, render_queue,
+    router::check_report,
+    webhook::{self, RunStatus},
+    Report, ReportType, SmtpConfig,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+use tokio::time::{sleep, Duration};
+
+/// Longest time between two scheduler ticks, so changed definitions are picked up
+const MAX_TICK: Duration = Duration::from_secs(30);
+
+/// What to do with a report whose schedules were missed, e.g. while the server was down
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MisfirePolicy {
+    /// Runs the report once for all missed schedules
+    RunOnce,
+    /// Waits for the next schedule
+    Skip,
+}
+
+/// Misfire policy from `ZO_SCHEDULER_MISFIRE_POLICY`
+pub static MISFIRE_POLICY: Lazy<MisfirePolicy> =
+    Lazy::new(
+        || match CONFIG.scheduler.misfire_policy.to_lowercase().as_str() {
+            "run_once" | "" => MisfirePolicy::RunOnce,
+            "skip" => MisfirePolicy::Skip,
+            other => panic!("Unknown misfire policy {other}, expected run_once or skip"),
+        },
+    );
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// A report run on a cron schedule, loaded from `ZO_SCHEDULER_PATH`
+#[derive(Deserialize, Debug, Clone)]
+pub struct ScheduledReport {
+    pub org_id: String,
+    pub name: String,
+    /// Cron expression, `min hour day month weekday` with an optional leading seconds field
+    pub cron: String,
+    /// Timezone of the schedule and the report
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    /// Body of the send endpoint
+    pub report: Report,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+impl ScheduledReport {
+    fn key(&self) -> String {
+        format!("{}/{}", self.org_id, self.name)
+    }
+}
+
+/// A definitions file holds one scheduled report or a list of them
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DefinitionsFile {
+    One(Box<ScheduledReport>),
+    Many(Vec<ScheduledReport>),
+}
+
+struct Entry {
+    definition: ScheduledReport,
+    schedule: Schedule,
+    timezone: Tz,
+}
+
+/// Parses a cron expression, five field expressions run at second 0
+fn parse_cron(expr: &str) -> Result<Schedule, anyhow::Error> {
+    let expr = if expr.split_whitespace().count() == 5 {
+        format!("0 {expr}")
+    } else {
+        expr.to_string()
+    };
+    Schedule::from_str(&expr).map_err(|e| anyhow::anyhow!("Invalid cron expression {expr}: {e}"))
+}
+
+fn entry(definition: ScheduledReport) -> Result<Entry, anyhow::Error> {
+    let schedule = parse_cron(&definition.cron)?;
+    let timezone = definition
+        .timezone
+        .parse::<Tz>()
+        .map_err(|e| anyhow::anyhow!("Invalid timezone {}: {e}", definition.timezone))?;
+    check_report(&definition.report, &definition.org_id, &definition.name)
+        .map_err(|(_, msg)| anyhow::anyhow!("Invalid report: {msg}"))?;
+    Ok(Entry {
+        definition,
+        schedule,
+        timezone,
+    })
+}
+
+/// The definition files with their modification time
+fn definition_files() -> Vec<(PathBuf, Option<SystemTime>)> {
+    let path = Path::new(&CONFIG.scheduler.path);
+    let mut files = if path.is_dir() {
+        match std::fs::read_dir(path) {
+            Ok(dir) => dir
+                .flatten()
+                .map(|file| file.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .collect(),
+            Err(e) => {
+                log::error!("Error reading scheduler dir {}: {e}", path.display());
+                vec![]
+            }
+        }
+    } else if path.exists() {
+        vec![path.to_path_buf()]
+    } else {
+        vec![]
+    };
+    files.sort();
+    files
+        .into_iter()
+        .map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+/// Loads the enabled scheduled reports, invalid definitions are logged and left out
+fn load(files: &[(PathBuf, Option<SystemTime>)]) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = vec![];
+    for (path, _) in files {
+        let definitions = std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(serde_json::from_slice::<DefinitionsFile>(&data)?));
+        let definitions = match definitions {
+            Ok(DefinitionsFile::One(definition)) => vec![*definition],
+            Ok(DefinitionsFile::Many(definitions)) => definitions,
+            Err(e) => {
+                log::error!("Error reading scheduled reports {}: {e}", path.display());
+                continue;
+            }
+        };
+        for definition in definitions.into_iter().filter(|d| d.enabled) {
+            let key = definition.key();
+            if entries.iter().any(|e| e.definition.key() == key) {
+                log::error!("Scheduled report {key} is defined more than once, skipping it");
+                continue;
+            }
+            match entry(definition) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => log::error!("Error in scheduled report {key}: {e}"),
+            }
+        }
+    }
+    log::info!("loaded {} scheduled reports", entries.len());
+    entries
+}
+
+fn state_path() -> PathBuf {
+    PathBuf::from(&CONFIG.scheduler.state_dir).join("state.json")
+}
+
+/// Last schedule which was handled per report, in microseconds
+fn load_state() -> HashMap<String, i64> {
+    match std::fs::read(state_path()) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+            log::error!("Error parsing scheduler state, starting afresh: {e}");
+            HashMap::new()
+        }),
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::error!("Error reading scheduler state: {e}");
+            }
+            HashMap::new()
+        }
+    }
+}
+
+fn save_state(state: &HashMap<String, i64>) {
+    let result = std::fs::create_dir_all(&CONFIG.scheduler.state_dir).and_then(|_| {
+        let tmp = state_path().with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+        std::fs::rename(tmp, state_path())
+    });
+    if let Err(e) = result {
+        log::error!("Error saving scheduler state: {e}");
+    }
+}
+
+/// Result of [`trigger`]
+enum Triggered {
+    /// Id of the started job
+    Started(String),
+    /// The render queue is full, the run is retried on the next tick
+    QueueFull,
+    /// A limit of the org rejected the run, it is retried after the given seconds
+    Limited(u64),
+}
+
+/// Submits the report as a job, like the send endpoint
+fn trigger(definition: &ScheduledReport) -> Result<Triggered, anyhow::Error> {
+    let (org_id, report_name) = (&definition.org_id, &definition.name);
+    let report = definition.report.clone();
+    let (dashboard, report_type) =
+        check_report(&report, org_id, report_name).map_err(|(_, msg)| anyhow::anyhow!("{msg}"))?;
+    let smtp_config = if report_type == ReportType::Cache {
+        None
+    } else {
+        Some(SmtpConfig::for_report(
+            org_id,
+            report.email_details.smtp_profile.as_deref(),
+        )?)
+    };
+    let ticket = match render_queue::enqueue(org_id) {
+        Ok(ticket) => ticket,
+        Err(e) => {
+            log::warn!("[{org_id}/{report_name}] scheduled report delayed: {e}");
+            return Ok(Triggered::QueueFull);
+        }
+    };
+    let admission = match limits::admit(org_id, report.email_details.recipients.len()) {
+        Ok(admission) => admission,
+        Err(rejection) => {
+            log::warn!("[{org_id}/{report_name}] scheduled report delayed: {rejection}");
+            return Ok(Triggered::Limited(rejection.retry_after_secs));
+        }
+    };
+    let job = jobs::submit(JobRequest {
+        org_id: org_id.clone(),
+        report_name: report_name.clone(),
+        report,
+        dashboard,
+        report_type,
+        timezone: definition.timezone.clone(),
+        smtp_config,
+        ticket,
+        admission,
+    });
+    Ok(Triggered::Started(job.id))
+}
+
+/// Schedules after `last` up to `now`, returns the latest one and their count
+fn due(
+    schedule: &Schedule,
+    timezone: &Tz,
+    last: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Option<(DateTime<Utc>, usize)> {
+    schedule
+        .after(&last.with_timezone(timezone))
+        .map(|t| t.with_timezone(&Utc))
+        .take_while(|t| *t <= now)
+        .fold(None, |acc, t| Some((t, acc.map_or(1, |(_, n)| n + 1))))
+}
+
+/// Reports a scheduled run which could not be started to the callback url of the report
+fn notify_failed(definition: &ScheduledReport, e: &anyhow::Error) {
+    let mut status = RunStatus::new(&definition.org_id, &definition.name);
+    status.fail(e);
+    webhook::notify(definition.report.callback_url.clone(), status);
+}
+
+/// Runs the scheduled reports of `ZO_SCHEDULER_PATH` on time. A report is not run while its
+/// previous run is still going, and reports whose schedules were missed are handled by
+/// `ZO_SCHEDULER_MISFIRE_POLICY`. Runs rejected by a limit of the org stay due and are
+/// retried once the limit allows it, so they are handled like missed schedules. The
+/// definitions are reloaded when their files change.
+pub async fn run() {
+    log::info!("report scheduler started for {}", CONFIG.scheduler.path);
+    let grace = chrono::Duration::seconds(CONFIG.scheduler.misfire_grace_secs as i64);
+    let mut files = definition_files();
+    let mut entries = load(&files);
+    let mut state = load_state();
+    // Job of the last run per report
+    let mut running: HashMap<String, String> = HashMap::new();
+    // Reports rejected by a limit, with the time they are retried
+    let mut limited: HashMap<String, DateTime<Utc>> = HashMap::new();
+
+    loop {
+        let current_files = definition_files();
+        if current_files != files {
+            files = current_files;
+            entries = load(&files);
+        }
+
+        let now = Utc::now();
+        let mut changed = false;
+        for entry in entries.iter() {
+            let definition = &entry.definition;
+            let key = definition.key();
+            let Some(last) = state
+                .get(&key)
+                .and_then(|t| DateTime::from_timestamp_micros(*t))
+            else {
+                // New reports start with their next schedule
+                state.insert(key, now.timestamp_micros());
+                changed = true;
+                continue;
+            };
+            let Some((scheduled, count)) = due(&entry.schedule, &entry.timezone, last, now) else {
+                continue;
+            };
+            let skip_missed = now - scheduled > grace && *MISFIRE_POLICY == MisfirePolicy::Skip;
+            if !skip_missed && limited.get(&key).is_some_and(|retry_at| *retry_at > now) {
+                continue;
+            }
+            limited.remove(&key);
+
+            if skip_missed {
+                log::warn!("[{key}] missed {count} schedules, waiting for the next schedule");
+            } else if running
+                .get(&key)
+                .and_then(|id| jobs::get(id))
+                .is_some_and(|job| !job.status.is_finished())
+            {
+                log::warn!(
+                    "[{key}] previous run is still running, skipping the schedule at {scheduled}"
+                );
+            } else {
+                if count > 1 {
+                    log::warn!("[{key}] missed {} schedules, running once", count - 1);
+                }
+                match trigger(definition) {
+                    Ok(Triggered::Started(job_id)) => {
+                        log::info!(
+                            "[{key}] scheduled report for {scheduled} started as job {job_id}"
+                        );
+                        running.insert(key.clone(), job_id);
+                    }
+                    Ok(Triggered::QueueFull) => continue,
+                    Ok(Triggered::Limited(retry_after_secs)) => {
+                        let retry_at = now + chrono::Duration::seconds(retry_after_secs as i64);
+                        limited.insert(key, retry_at);
+                        continue;
+                    }
+                    // Invalid reports fail again on every retry, so the schedule is given up
+                    Err(e) => {
+                        log::error!("[{key}] error starting scheduled report: {e}");
+                        notify_failed(definition, &e);
+                    }
+                }
+            }
+            state.insert(key, scheduled.timestamp_micros());
+            changed = true;
+        }
+        if changed {
+            state.retain(|key, _| entries.iter().any(|e| e.definition.key() == *key));
+            save_state(&state);
+        }
+
+        let now = Utc::now();
+        let next = entries
+            .iter()
+            .filter_map(|entry| {
+                entry
+                    .schedule
+                    .after(&now.with_timezone(&entry.timezone))
+                    .next()
+            })
+            .map(|t| (t.with_timezone(&Utc) - now).to_std().unwrap_or_default())
+            .min()
+            .unwrap_or(MAX_TICK);
+        sleep(next.clamp(Duration::from_millis(100), MAX_TICK)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn parse_cron_runs_five_fields_at_second_zero() {
+        let schedule = parse_cron("30 8 * * Mon").unwrap();
+        let next = schedule.after(&utc("2026-10-18T00:00:00Z")).next().unwrap();
+        assert_eq!(next, utc("2026-10-19T08:30:00Z"));
+    }
+
+    #[test]
+    fn parse_cron_keeps_six_fields() {
+        let schedule = parse_cron("15 30 8 * * *").unwrap();
+        let next = schedule.after(&utc("2026-10-18T00:00:00Z")).next().unwrap();
+        assert_eq!(next, utc("2026-10-18T08:30:15Z"));
+    }
+
+    #[test]
+    fn parse_cron_rejects_invalid_expressions() {
+        assert!(parse_cron("61 * * * *").is_err());
+        assert!(parse_cron("every day").is_err());
+    }
+
+    #[test]
+    fn due_is_none_before_the_next_schedule() {
+        let schedule = parse_cron("0 8 * * *").unwrap();
+        let last = utc("2026-10-18T08:00:00Z");
+        assert_eq!(
+            due(&schedule, &Tz::UTC, last, utc("2026-10-19T07:59:59Z")),
+            None
+        );
+        assert_eq!(
+            due(&schedule, &Tz::UTC, last, utc("2026-10-19T08:00:00Z")),
+            Some((utc("2026-10-19T08:00:00Z"), 1))
+        );
+    }
+
+    #[test]
+    fn due_counts_the_missed_schedules() {
+        let schedule = parse_cron("0 * * * *").unwrap();
+        let due = due(
+            &schedule,
+            &Tz::UTC,
+            utc("2026-10-18T08:00:00Z"),
+            utc("2026-10-18T11:30:00Z"),
+        );
+        assert_eq!(due, Some((utc("2026-10-18T11:00:00Z"), 3)));
+    }
+
+    #[test]
+    fn due_follows_the_timezone() {
+        let schedule = parse_cron("0 8 * * *").unwrap();
+        let due = due(
+            &schedule,
+            &chrono_tz::Europe::Berlin,
+            utc("2026-10-18T00:00:00Z"),
+            utc("2026-10-18T12:00:00Z"),
+        );
+        assert_eq!(due, Some((utc("2026-10-18T06:00:00Z"), 1)));
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::config::CONFIG;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    Error,
+};
+use opentelemetry::{
+    propagation::{Extractor, TextMapPropagator},
+    trace::{TraceContextExt, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::{WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator, runtime, trace::TracerProvider, Resource,
+};
+use std::collections::HashMap;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+pub const TRACE_ID_HEADER: &str = "x-trace-id";
+
+/// Sets up the tracer. Traces are only exported with `ZO_TRACING_ENABLED`, but trace ids are
+/// always generated so responses and logs can be correlated.
+pub fn init() -> Result<(), anyhow::Error> {
+    let mut builder = TracerProvider::builder().with_resource(Resource::new(vec![KeyValue::new(
+        "service.name",
+        CONFIG.common.app_name.clone(),
+    )]));
+    if CONFIG.tracing.enabled {
+        let mut headers = HashMap::new();
+        if !CONFIG.tracing.header_value.is_empty() {
+            headers.insert(
+                CONFIG.tracing.header_key.clone(),
+                CONFIG.tracing.header_value.clone(),
+            );
+        }
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&CONFIG.tracing.otlp_url)
+            .with_headers(headers)
+            .build()?;
+        builder = builder.with_batch_exporter(exporter, runtime::Tokio);
+        log::info!("exporting traces to {}", CONFIG.tracing.otlp_url);
+    }
+    let provider = builder.build();
+    let tracer = provider.tracer("o2_report_generator");
+    opentelemetry::global::set_tracer_provider(provider);
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(())
+}
+
+/// Flushes the spans which were not exported yet
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Trace id of the current span
+pub fn current_trace_id() -> Option<String> {
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Middleware running the request in a span, continuing the trace of a W3C `traceparent`
+/// header, and returning the trace id in the `x-trace-id` header
+pub async fn trace_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let span = tracing::info_span!(
+        "http_request",
+        otel.name = format!("{} {}", req.method(), req.path()),
+        http.method = %req.method(),
+        http.target = req.path(),
+    );
+    let parent = TraceContextPropagator::new().extract(&HeaderExtractor(req.headers()));
+    span.set_parent(parent);
+
+    let trace_id = span
+        .in_scope(current_trace_id)
+        .and_then(|id| HeaderValue::from_str(&id).ok());
+    let mut res = next.call(req).instrument(span).await?;
+    if let Some(trace_id) = trace_id {
+        res.headers_mut()
+            .insert(HeaderName::from_static(TRACE_ID_HEADER), trace_id);
+    }
+    Ok(res)
+}
@@ -0,0 +1,331 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{config::CONFIG, EmailDetails, ReportDashboard};
+use chrono_tz::Tz;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use utoipa::ToSchema;
+
+const DEFAULT_SUBJECT: &str = "Openobserve Report - {{title}}";
+const DEFAULT_HTML_BODY: &str = "<p>{{message}}</p>{{inline_image}}<p><a href='{{dashboard_url}}' target='_blank'>Link to dashboard</a></p>";
+const DEFAULT_TEXT_BODY: &str = "{{message}}\n\nLink to dashboard: {{dashboard_url}}\n";
+
+/// Server-wide default template, loaded from `ZO_EMAIL_TEMPLATE_FILE`.
+/// Parts that are not set fall back to the built-in templates.
+pub static DEFAULT_EMAIL_TEMPLATE: Lazy<EmailTemplate> = Lazy::new(|| {
+    let path = &CONFIG.smtp.email_template_file;
+    if path.is_empty() {
+        return EmailTemplate::default();
+    }
+    EmailTemplate::load(path).unwrap_or_else(|e| panic!("{e}"))
+});
+
+/// Templates for the report email. Every part is optional, missing parts are taken
+/// from the server-wide default template.
+///
+/// Placeholders are written as `{{name}}`, unknown placeholders are kept as is.
+/// Values are html escaped in the html body unless the report opts in with
+/// [`EmailDetails::trusted_html`].
+/// Available placeholders: `report_name`, `title`, `message`, `org_id`, `dashboard`,
+/// `folder`, `tab`, `timezone`, `from`, `to`, `variables`, `var.<key>` and `dashboard_url`.
+/// The html body can also use `inline_image` to position the inline attachment or preview.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, ToSchema)]
+pub struct EmailTemplate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html_body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_body: Option<String>,
+}
+
+impl EmailTemplate {
+    /// Reads a template from a json file with the optional `subject`, `html_body` and
+    /// `text_body` parts
+    pub fn load(path: &str) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Error reading email template file {path}: {e}"))?;
+        serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Error parsing email template file {path}: {e}"))
+    }
+
+    pub fn subject(&self) -> &str {
+        self.subject
+            .as_deref()
+            .or(DEFAULT_EMAIL_TEMPLATE.subject.as_deref())
+            .unwrap_or(DEFAULT_SUBJECT)
+    }
+
+    pub fn html_body(&self) -> &str {
+        self.html_body
+            .as_deref()
+            .or(DEFAULT_EMAIL_TEMPLATE.html_body.as_deref())
+            .unwrap_or(DEFAULT_HTML_BODY)
+    }
+
+    pub fn text_body(&self) -> &str {
+        self.text_body
+            .as_deref()
+            .or(DEFAULT_EMAIL_TEMPLATE.text_body.as_deref())
+            .unwrap_or(DEFAULT_TEXT_BODY)
+    }
+}
+
+/// Values substituted into an [`EmailTemplate`].
+#[derive(Debug, Default, Clone)]
+pub struct TemplateVars {
+    values: HashMap<String, String>,
+    /// Keys whose values are trusted html and are not escaped in the html body
+    html_keys: HashSet<String>,
+}
+
+impl TemplateVars {
+    /// Builds the placeholder values of a rendered report.
+    /// `from` and `to` are in microseconds and are formatted in the report `timezone`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn for_report(
+        org_id: &str,
+        report_name: &str,
+        dashboard: &ReportDashboard,
+        email_details: &EmailDetails,
+        timezone: &str,
+        from: i64,
+        to: i64,
+        dashboard_url: &str,
+    ) -> Self {
+        let mut vars = Self::default();
+        vars.insert("report_name", report_name);
+        vars.insert("title", &email_details.title);
+        if email_details.trusted_html {
+            vars.insert_html("message", &email_details.message);
+        } else {
+            vars.insert("message", &email_details.message);
+        }
+        vars.insert("org_id", org_id);
+        vars.insert("dashboard", &dashboard.dashboard);
+        vars.insert("folder", &dashboard.folder);
+        vars.insert("tab", dashboard.tabs.first().map_or("", |t| t.as_str()));
+        vars.insert("timezone", timezone);
+        vars.insert("from", &format_timestamp(from, timezone));
+        vars.insert("to", &format_timestamp(to, timezone));
+        vars.insert("dashboard_url", dashboard_url);
+
+        let variables = dashboard
+            .variables
+            .iter()
+            .map(|v| format!("{}={}", v.key, v.value))
+            .collect::<Vec<_>>()
+            .join(", ");
+        vars.insert("variables", &variables);
+        for variable in dashboard.variables.iter() {
+            vars.insert(&format!("var.{}", variable.key), &variable.value);
+        }
+        vars
+    }
+
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.html_keys.remove(key);
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    /// Inserts a value which is already html. It is used as is in the html body and
+    /// its tags are stripped in the plain-text body.
+    pub fn insert_html(&mut self, key: &str, value: &str) {
+        self.html_keys.insert(key.to_string());
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    /// Renders a plain-text template such as the subject or the text body.
+    pub fn render(&self, template: &str) -> String {
+        self.render_with(template, |key, value| {
+            if self.html_keys.contains(key) {
+                strip_html_tags(value)
+            } else {
+                value.to_string()
+            }
+        })
+    }
+
+    /// Renders an html template, escaping every value not inserted with [`Self::insert_html`].
+    pub fn render_html(&self, template: &str) -> String {
+        self.render_with(template, |key, value| {
+            if self.html_keys.contains(key) {
+                value.to_string()
+            } else {
+                escape_html(value)
+            }
+        })
+    }
+
+    /// Replaces every known `{{name}}` placeholder of the template.
+    fn render_with(&self, template: &str, format_value: impl Fn(&str, &str) -> String) -> String {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let Some(end) = after.find("}}") else {
+                rest = &rest[start..];
+                break;
+            };
+            let key = after[..end].trim();
+            match self.values.get(key) {
+                Some(value) => out.push_str(&format_value(key, value)),
+                None => out.push_str(&rest[start..start + end + 4]),
+            }
+            rest = &after[end + 2..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// Formats a microsecond timestamp in the given IANA timezone, falling back to UTC.
+fn format_timestamp(micros: i64, timezone: &str) -> String {
+    let tz: Tz = timezone.parse().unwrap_or_else(|_| {
+        log::warn!("Unknown timezone {timezone}, formatting report times in UTC");
+        Tz::UTC
+    });
+    match chrono::DateTime::from_timestamp_micros(micros) {
+        Some(t) => t
+            .with_timezone(&tz)
+            .format("%Y-%m-%d %H:%M:%S %Z")
+            .to_string(),
+        None => micros.to_string(),
+    }
+}
+
+pub fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Removes html tags and decodes the common entities, for the plain-text body.
+fn strip_html_tags(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut in_tag = false;
+    for c in s.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn vars() -> TemplateVars {
+        let mut vars = TemplateVars::default();
+        vars.insert("title", "Weekly <errors>");
+        vars.insert(
+            "dashboard_url",
+            "https://o2.example.com/web/dashboards?a=1&b=2",
+        );
+        vars
+    }
+
+    fn template_file(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn render_replaces_known_placeholders() {
+        assert_eq!(
+            vars().render("Report - {{title}} ({{ title }})"),
+            "Report - Weekly <errors> (Weekly <errors>)"
+        );
+    }
+
+    #[test]
+    fn render_keeps_unknown_and_unclosed_placeholders() {
+        assert_eq!(
+            vars().render("{{unknown}} {{title}} {{title"),
+            "{{unknown}} Weekly <errors> {{title"
+        );
+    }
+
+    #[test]
+    fn render_html_escapes_values() {
+        let mut vars = vars();
+        vars.insert_html("message", "<b>Hi</b> &amp; bye");
+        assert_eq!(
+            vars.render_html("<p>{{title}}</p>{{message}}<a href='{{dashboard_url}}'>"),
+            "<p>Weekly &lt;errors&gt;</p><b>Hi</b> &amp; bye\
+             <a href='https://o2.example.com/web/dashboards?a=1&amp;b=2'>"
+        );
+        assert_eq!(vars.render("{{message}}"), "Hi & bye");
+    }
+
+    #[test]
+    fn missing_parts_use_the_built_in_templates() {
+        let template = EmailTemplate {
+            subject: Some("{{title}}".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(template.subject(), "{{title}}");
+        assert_eq!(template.html_body(), DEFAULT_HTML_BODY);
+        assert_eq!(template.text_body(), DEFAULT_TEXT_BODY);
+    }
+
+    #[test]
+    fn load_reads_a_partial_template() {
+        let file = template_file(r#"{"text_body": "{{message}}"}"#);
+        let template = EmailTemplate::load(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(template.subject, None);
+        assert_eq!(template.text_body.as_deref(), Some("{{message}}"));
+    }
+
+    #[test]
+    fn load_fails_on_a_missing_file() {
+        let err = EmailTemplate::load("/nonexistent/template.json").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Error reading email template file"));
+    }
+
+    #[test]
+    fn load_fails_on_an_invalid_template() {
+        let file = template_file(r#"{"subject": 1}"#);
+        let err = EmailTemplate::load(file.path().to_str().unwrap()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Error parsing email template file"));
+    }
+}
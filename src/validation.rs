@@ -0,0 +1,456 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{
+    config::CONFIG, webhook, EmailAttachmentType, Report, ReportDashboard, ReportTimerangeType,
+    ReportType, SmtpConfig,
+};
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use serde::Serialize;
+use tokio::time::Duration;
+use utoipa::ToSchema;
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(CONFIG.readiness.timeout_secs))
+        .build()
+        .expect("Error building the validation http client")
+});
+
+/// A problem with one field of the report, `field` is its path in the payload,
+/// e.g. `dashboards[0].timerange.period`
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Result of the validate endpoint
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Validation {
+    pub valid: bool,
+    pub errors: Vec<FieldError>,
+}
+
+impl Validation {
+    pub fn new(errors: Vec<FieldError>) -> Self {
+        Self {
+            valid: errors.is_empty(),
+            errors,
+        }
+    }
+}
+
+/// Checks the whole report without rendering it, returns all problems found
+pub fn check(report: &Report, org_id: &str, timezone: Option<&str>) -> Vec<FieldError> {
+    let mut errors = vec![];
+    let sends_email = !report.email_details.recipients.is_empty();
+
+    if let Some(timezone) = timezone {
+        if timezone.parse::<chrono_tz::Tz>().is_err() {
+            errors.push(FieldError::new(
+                "timezone",
+                format!("Unknown timezone {timezone}"),
+            ));
+        }
+    }
+
+    if report.dashboards.is_empty() {
+        errors.push(FieldError::new(
+            "dashboards",
+            "At least 1 dashboard must be provided",
+        ));
+    }
+    for (i, dashboard) in report.dashboards.iter().enumerate() {
+        check_dashboard(
+            dashboard,
+            &format!("dashboards[{i}]"),
+            sends_email,
+            &mut errors,
+        );
+    }
+
+    for (i, recipient) in report.email_details.recipients.iter().enumerate() {
+        if let Err(e) = recipient.parse::<lettre::message::Mailbox>() {
+            errors.push(FieldError::new(
+                format!("email_details.recipients[{i}]"),
+                format!("Invalid email address {recipient}: {e}"),
+            ));
+        }
+    }
+    if sends_email {
+        if let Err(e) = SmtpConfig::for_report(org_id, report.email_details.smtp_profile.as_deref())
+        {
+            errors.push(FieldError::new("email_details.smtp_profile", e.to_string()));
+        }
+    }
+
+    if let Some(url) = &report.callback_url {
+        if let Err(e) = webhook::validate_url(url) {
+            errors.push(FieldError::new("callback_url", e.to_string()));
+        }
+    }
+    errors
+}
+
+fn check_dashboard(
+    dashboard: &ReportDashboard,
+    path: &str,
+    sends_email: bool,
+    errors: &mut Vec<FieldError>,
+) {
+    if dashboard.dashboard.trim().is_empty() {
+        errors.push(FieldError::new(
+            format!("{path}.dashboard"),
+            "The dashboard id must not be empty",
+        ));
+    }
+    if dashboard.folder.trim().is_empty() {
+        errors.push(FieldError::new(
+            format!("{path}.folder"),
+            "The folder id must not be empty",
+        ));
+    }
+    if dashboard.tabs.is_empty() {
+        errors.push(FieldError::new(
+            format!("{path}.tabs"),
+            "At least 1 tab must be provided",
+        ));
+    }
+    for (i, tab) in dashboard.tabs.iter().enumerate() {
+        if tab.trim().is_empty() {
+            errors.push(FieldError::new(
+                format!("{path}.tabs[{i}]"),
+                "The tab id must not be empty",
+            ));
+        }
+    }
+    for (i, variable) in dashboard.variables.iter().enumerate() {
+        if variable.key.trim().is_empty() {
+            errors.push(FieldError::new(
+                format!("{path}.variables[{i}].key"),
+                "The variable key must not be empty",
+            ));
+        }
+    }
+
+    if let Err(e) = dashboard.timerange.resolve() {
+        let field = match dashboard.timerange.range_type {
+            ReportTimerangeType::Relative => format!("{path}.timerange.period"),
+            ReportTimerangeType::Absolute => format!("{path}.timerange"),
+        };
+        errors.push(FieldError::new(field, e.detail()));
+    }
+
+    let dimensions = &dashboard.attachment_dimensions;
+    if dimensions.height == 0 || dimensions.width == 0 {
+        errors.push(FieldError::new(
+            format!("{path}.attachment_dimensions"),
+            "The height and width must be greater than 0",
+        ));
+    }
+
+    if sends_email && dashboard.email_attachment_type == EmailAttachmentType::Inline {
+        match dashboard.report_type {
+            ReportType::PDF | ReportType::Csv => errors.push(FieldError::new(
+                format!("{path}.email_attachment_type"),
+                format!(
+                    "{} reports can't be sent inline, most email servers only embed images, use png",
+                    dashboard.report_type.as_str()
+                ),
+            )),
+            ReportType::PNG | ReportType::Cache => {}
+        }
+    }
+}
+
+/// O2 api url next to the web url, e.g. `http://localhost:5080/api` for
+/// `http://localhost:5080/web`
+fn api_url() -> Result<Url, anyhow::Error> {
+    let web_url = CONFIG.common.o2_web_uri.trim_end_matches('/');
+    let base = web_url.strip_suffix("/web").unwrap_or(web_url);
+    Url::parse(&format!("{base}/api")).map_err(|e| anyhow::anyhow!("Invalid ZO_O2_APP_URL: {e}"))
+}
+
+/// Url of an O2 api endpoint. The segments and the query come from the report, so they are
+/// escaped and can't point the request at another endpoint.
+fn api_endpoint(api_url: &Url, segments: &[&str], query: &[(&str, &str)]) -> Url {
+    let mut url = api_url.clone();
+    url.path_segments_mut()
+        .expect("http urls have a path")
+        .pop_if_empty()
+        .extend(segments);
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+    url
+}
+
+/// Requests the O2 api as the report user, `None` when the resource was not found
+async fn get_json(url: Url) -> Result<Option<serde_json::Value>, anyhow::Error> {
+    let resp = HTTP_CLIENT
+        .get(url)
+        .basic_auth(&CONFIG.auth.user_email, Some(&CONFIG.auth.user_password))
+        .send()
+        .await?;
+    match resp.status() {
+        reqwest::StatusCode::NOT_FOUND => Ok(None),
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => Err(anyhow::anyhow!(
+            "The report user could not log in to O2: {}",
+            resp.status()
+        )),
+        _ => Ok(Some(resp.error_for_status()?.json().await?)),
+    }
+}
+
+/// Ids of the tabs in a dashboard of the O2 api. The dashboard is nested by its version,
+/// so the tabs are looked up anywhere in it.
+fn tab_ids(value: &serde_json::Value, ids: &mut Vec<String>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let Some(serde_json::Value::Array(tabs)) = map.get("tabs") {
+                ids.extend(
+                    tabs.iter()
+                        .filter_map(|tab| tab.get("tabId").and_then(|id| id.as_str()))
+                        .map(|id| id.to_string()),
+                );
+            }
+            map.values().for_each(|v| tab_ids(v, ids));
+        }
+        serde_json::Value::Array(values) => values.iter().for_each(|v| tab_ids(v, ids)),
+        _ => {}
+    }
+}
+
+/// Logs in to O2 as the report user and checks that the folder, the dashboard and its tabs
+/// exist. Dashboards without an id or folder are left out.
+pub async fn check_reachability(report: &Report, org_id: &str) -> Vec<FieldError> {
+    let mut errors = vec![];
+    let api_url = match api_url() {
+        Ok(url) => url,
+        Err(e) => return vec![FieldError::new("dashboards", e.to_string())],
+    };
+    for (i, dashboard) in report.dashboards.iter().enumerate() {
+        let path = format!("dashboards[{i}]");
+        let (dashboard_id, folder_id) = (dashboard.dashboard.trim(), dashboard.folder.trim());
+        if dashboard_id.is_empty() || folder_id.is_empty() {
+            continue;
+        }
+
+        let url = api_endpoint(&api_url, &[org_id, "folders", folder_id], &[]);
+        match get_json(url).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                errors.push(FieldError::new(
+                    format!("{path}.folder"),
+                    format!("Folder {folder_id} not found in org {org_id}"),
+                ));
+                continue;
+            }
+            Err(e) => {
+                errors.push(FieldError::new(
+                    path,
+                    format!("Error checking folder {folder_id}: {e}"),
+                ));
+                continue;
+            }
+        }
+
+        let url = api_endpoint(
+            &api_url,
+            &[org_id, "dashboards", dashboard_id],
+            &[("folder", folder_id)],
+        );
+        let found = match get_json(url).await {
+            Ok(Some(found)) => found,
+            Ok(None) => {
+                errors.push(FieldError::new(
+                    format!("{path}.dashboard"),
+                    format!("Dashboard {dashboard_id} not found in folder {folder_id}"),
+                ));
+                continue;
+            }
+            Err(e) => {
+                errors.push(FieldError::new(
+                    path,
+                    format!("Error checking dashboard {dashboard_id}: {e}"),
+                ));
+                continue;
+            }
+        };
+        let mut tabs = vec![];
+        tab_ids(&found, &mut tabs);
+        // Dashboards of older O2 versions have no tabs
+        if tabs.is_empty() {
+            continue;
+        }
+        for (j, tab) in dashboard.tabs.iter().enumerate() {
+            if !tab.trim().is_empty() && !tabs.iter().any(|t| t == tab) {
+                errors.push(FieldError::new(
+                    format!("{path}.tabs[{j}]"),
+                    format!("Tab {tab} not found in dashboard {dashboard_id}"),
+                ));
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn report(dashboard: serde_json::Value, recipients: &[&str]) -> Report {
+        let mut value = json!({
+            "dashboard": "dash",
+            "folder": "default",
+            "tabs": ["default"],
+            "attachment_dimensions": {"height": 720, "width": 1280},
+        });
+        value
+            .as_object_mut()
+            .unwrap()
+            .extend(dashboard.as_object().unwrap().clone());
+        serde_json::from_value(json!({
+            "dashboards": [value],
+            "email_details": {
+                "recipients": recipients,
+                "title": "Weekly",
+                "name": "weekly",
+                "message": "",
+            },
+        }))
+        .unwrap()
+    }
+
+    fn report_with_tabs(tabs: &[&str]) -> Report {
+        report(json!({ "tabs": tabs }), &[])
+    }
+
+    fn fields(errors: Vec<FieldError>) -> Vec<String> {
+        errors.into_iter().map(|e| e.field).collect()
+    }
+
+    #[test]
+    fn valid_reports_have_no_errors() {
+        let report = report(json!({}), &[]);
+        assert!(check(&report, "default", Some("Europe/Berlin")).is_empty());
+    }
+
+    #[test]
+    fn dashboard_fields_are_checked() {
+        let report = report(
+            json!({
+                "dashboard": " ",
+                "tabs": [],
+                "attachment_dimensions": {"height": 0, "width": 1280},
+                "timerange": {"type": "relative", "period": "1x", "from": 0, "to": 0},
+            }),
+            &[],
+        );
+        assert_eq!(
+            fields(check(&report, "default", None)),
+            [
+                "dashboards[0].dashboard",
+                "dashboards[0].tabs",
+                "dashboards[0].timerange.period",
+                "dashboards[0].attachment_dimensions",
+            ]
+        );
+
+        let empty_tab = report_with_tabs(&["default", ""]);
+        assert_eq!(
+            fields(check(&empty_tab, "default", None)),
+            ["dashboards[0].tabs[1]"]
+        );
+    }
+
+    #[tokio::test]
+    async fn inline_attachments_must_be_images() {
+        let pdf = json!({"report_type": "pdf", "email_attachment_type": "inline"});
+        assert_eq!(
+            fields(check(
+                &report(pdf.clone(), &["a@example.com"]),
+                "default",
+                None
+            )),
+            ["dashboards[0].email_attachment_type"]
+        );
+        // Reports without recipients are not emailed
+        assert!(check(&report(pdf, &[]), "default", None).is_empty());
+
+        let png = json!({"report_type": "png", "email_attachment_type": "inline"});
+        assert!(check(&report(png, &["a@example.com"]), "default", None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn timezone_and_recipients_are_checked() {
+        let report = report(json!({}), &["a@example.com", "not an address"]);
+        assert_eq!(
+            fields(check(&report, "default", Some("Mars/Olympus"))),
+            ["timezone", "email_details.recipients[1]"]
+        );
+    }
+
+    #[test]
+    fn tab_ids_are_found_at_any_depth() {
+        let dashboard = json!({
+            "v5": {
+                "title": "dash",
+                "tabs": [{"tabId": "default"}, {"tabId": "errors", "panels": []}, {"name": "no id"}],
+            },
+            "hash": "1",
+        });
+        let mut ids = vec![];
+        tab_ids(&dashboard, &mut ids);
+        assert_eq!(ids, ["default", "errors"]);
+
+        let mut ids = vec![];
+        tab_ids(&json!({"v1": {"panels": []}}), &mut ids);
+        assert!(ids.is_empty());
+    }
+
+    #[test]
+    fn api_endpoints_escape_report_values() {
+        let api_url = Url::parse("http://localhost:5080/api").unwrap();
+        let url = api_endpoint(&api_url, &["default", "folders", "../../users"], &[]);
+        assert_eq!(
+            url.as_str(),
+            "http://localhost:5080/api/default/folders/..%2F..%2Fusers"
+        );
+
+        let url = api_endpoint(
+            &api_url,
+            &["default", "dashboards", "x?foo="],
+            &[("folder", "a&b=c")],
+        );
+        assert_eq!(url.path(), "/api/default/dashboards/x%3Ffoo=");
+        assert_eq!(url.query(), Some("folder=a%26b%3Dc"));
+
+        let url = api_endpoint(&api_url, &["default", "folders", ".."], &[]);
+        assert!(url.path().starts_with("/api/default/folders"), "{url}");
+    }
+}
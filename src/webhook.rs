@@ -0,0 +1,374 @@
+// Copyright 2025 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{
+    config::CONFIG,
+    email::{DeliveryStatus, RecipientStatus},
+    error::ReportError,
+    telemetry,
+};
+use once_cell::sync::Lazy;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect::Policy,
+    Url,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tokio::time::{sleep, Duration};
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(CONFIG.webhook.timeout_secs))
+        .redirect(Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+        .expect("Error building the webhook http client")
+});
+
+/// Url prefixes of `ZO_WEBHOOK_ALLOWED_URLS`
+pub static ALLOWED_URLS: Lazy<Vec<Url>> = Lazy::new(|| {
+    CONFIG
+        .webhook
+        .allowed_urls
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(|url| {
+            let parsed = Url::parse(url)
+                .unwrap_or_else(|e| panic!("Invalid ZO_WEBHOOK_ALLOWED_URLS url {url}: {e}"));
+            if !matches!(parsed.scheme(), "http" | "https") || parsed.host().is_none() {
+                panic!("Invalid ZO_WEBHOOK_ALLOWED_URLS url {url}: must be an http(s) url");
+            }
+            parsed
+        })
+        .collect()
+});
+
+/// Resolves callback hosts, failing for hosts with an internal address unless they are
+/// allowed by `ZO_WEBHOOK_ALLOWED_URLS`. The check is done on the addresses the request
+/// connects to, so a host can't resolve to a public address for the check and then to an
+/// internal one for the request.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            let allowed = ALLOWED_URLS
+                .iter()
+                .any(|url| url.host_str() == Some(host.as_str()));
+            if !allowed {
+                if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+                    return Err(format!("callback host {host} resolves to {}", addr.ip()).into());
+                }
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Whether the address is reachable on the internet, as opposed to loopback, private,
+/// link-local (which includes cloud metadata services) and other special addresses
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // Shared address space of carrier-grade NAT
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum RunOutcome {
+    Success,
+    Failure,
+}
+
+/// Status document posted to the callback url of a report when its run finishes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunStatus {
+    pub org_id: String,
+    pub report_name: String,
+    /// Set for async runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+    pub status: RunOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Custom code of the error, see [`ReportError::code`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<u16>,
+    pub recipients: Vec<RecipientStatus>,
+    /// Size of the rendered report in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub render_duration_ms: Option<u64>,
+    /// Dashboard url used for the report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dashboard_url: Option<String>,
+    /// Finish time in microseconds
+    pub finished_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+}
+
+impl RunStatus {
+    pub fn new(org_id: &str, report_name: &str) -> Self {
+        Self {
+            org_id: org_id.to_string(),
+            report_name: report_name.to_string(),
+            job_id: None,
+            status: RunOutcome::Failure,
+            error: None,
+            error_code: None,
+            recipients: vec![],
+            artifact_size: None,
+            render_duration_ms: None,
+            dashboard_url: None,
+            finished_at: 0,
+            trace_id: telemetry::current_trace_id(),
+        }
+    }
+
+    /// Records the delivery results, the run succeeds when any recipient was not failed
+    pub fn sent(&mut self, recipients: &[RecipientStatus]) {
+        self.recipients = recipients.to_vec();
+        if recipients
+            .iter()
+            .any(|r| r.status != DeliveryStatus::Failed)
+        {
+            self.succeed();
+        } else {
+            self.fail(&ReportError::from_recipients(recipients).into());
+        }
+    }
+
+    pub fn succeed(&mut self) {
+        self.status = RunOutcome::Success;
+        self.error = None;
+        self.error_code = None;
+    }
+
+    pub fn fail(&mut self, error: &anyhow::Error) {
+        self.status = RunOutcome::Failure;
+        self.error = Some(error.to_string());
+        self.error_code = ReportError::find(error).map(ReportError::code);
+    }
+}
+
+/// Checks that the callback url is an absolute http(s) url, which starts with one of
+/// `ZO_WEBHOOK_ALLOWED_URLS` when they are set, or else is not an internal address
+pub fn validate_url(url: &str) -> Result<(), anyhow::Error> {
+    check_url(url, &ALLOWED_URLS)
+}
+
+fn check_url(url: &str, allowed: &[Url]) -> Result<(), anyhow::Error> {
+    let parsed = Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid callback url {url}: {e}"))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(anyhow::anyhow!(
+            "Invalid callback url {url}: the scheme must be http or https"
+        ));
+    }
+    if !allowed.is_empty() {
+        if allowed.iter().any(|prefix| has_prefix(&parsed, prefix)) {
+            return Ok(());
+        }
+        return Err(anyhow::anyhow!(
+            "Invalid callback url {url}: not one of ZO_WEBHOOK_ALLOWED_URLS"
+        ));
+    }
+    let host = parsed.host_str().unwrap_or_default();
+    let internal = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => !is_public(ip),
+        Err(_) => {
+            let domain = host.trim_end_matches('.').to_ascii_lowercase();
+            domain.is_empty() || domain == "localhost" || domain.ends_with(".localhost")
+        }
+    };
+    if internal {
+        return Err(anyhow::anyhow!(
+            "Invalid callback url {url}: internal addresses are not allowed"
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the url has the scheme, host and port of the prefix and a path below its path
+fn has_prefix(url: &Url, prefix: &Url) -> bool {
+    let path = prefix.path().trim_end_matches('/');
+    url.scheme() == prefix.scheme()
+        && url.host() == prefix.host()
+        && url.port_or_known_default() == prefix.port_or_known_default()
+        && (url.path() == path || url.path().starts_with(&format!("{path}/")))
+}
+
+/// Posts the run status to the callback url in the background, retrying failed deliveries
+pub fn notify(url: Option<String>, mut status: RunStatus) {
+    let Some(url) = url else {
+        return;
+    };
+    // Urls of reports which were accepted before the allowed urls changed
+    if let Err(e) = validate_url(&url) {
+        log::error!(
+            "Run status of {}/{} not posted: {e}",
+            status.org_id,
+            status.report_name
+        );
+        return;
+    }
+    status.finished_at = chrono::Utc::now().timestamp_micros();
+    tokio::task::spawn(async move {
+        let max_attempts = CONFIG.webhook.max_retries + 1;
+        let mut backoff = Duration::from_secs(1);
+        for attempt in 1..=max_attempts {
+            let result = HTTP_CLIENT
+                .post(&url)
+                .json(&status)
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status());
+            match result {
+                Ok(_) => {
+                    log::info!(
+                        "[{}/{}] run status posted to {url}",
+                        status.org_id,
+                        status.report_name
+                    );
+                    return;
+                }
+                Err(e) if attempt < max_attempts => {
+                    log::warn!(
+                        "Error posting run status to {url} (attempt {attempt}/{max_attempts}), retrying in {}s: {e}",
+                        backoff.as_secs()
+                    );
+                    sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(e) => {
+                    log::error!(
+                        "Error posting run status of {}/{} to {url}: {e}",
+                        status.org_id,
+                        status.report_name
+                    );
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_urls_are_valid() {
+        for url in [
+            "https://hooks.example.com/reports",
+            "http://example.org:8080/x",
+            "http://8.8.8.8/",
+            "http://[2606:4700::1111]/",
+        ] {
+            assert!(check_url(url, &[]).is_ok(), "{url}");
+        }
+    }
+
+    #[test]
+    fn internal_urls_are_rejected() {
+        for url in [
+            "http://localhost:5080/",
+            "http://api.localhost/",
+            "http://127.0.0.1/",
+            "http://10.0.0.5/",
+            "http://172.16.0.1/",
+            "http://192.168.1.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[fe80::1]/",
+            "http://[fd00::1]/",
+            "http://[::ffff:127.0.0.1]/",
+        ] {
+            assert!(check_url(url, &[]).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn non_http_urls_are_rejected() {
+        for url in ["ftp://example.com/", "file:///etc/passwd", "not a url"] {
+            assert!(check_url(url, &[]).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn allowed_urls_restrict_the_callbacks() {
+        let allowed = [
+            Url::parse("https://hooks.example.com/reports/").unwrap(),
+            Url::parse("http://notifier.internal:8080").unwrap(),
+        ];
+        for url in [
+            "https://hooks.example.com/reports",
+            "https://hooks.example.com/reports/weekly?x=1",
+            "https://hooks.example.com:443/reports/weekly",
+            "http://notifier.internal:8080/any",
+        ] {
+            assert!(check_url(url, &allowed).is_ok(), "{url}");
+        }
+        for url in [
+            "https://hooks.example.com/reportsx",
+            "https://hooks.example.com/other",
+            "http://hooks.example.com/reports",
+            "https://hooks.example.com:8443/reports",
+            "http://notifier.internal/any",
+            "https://example.com/reports",
+        ] {
+            assert!(check_url(url, &allowed).is_err(), "{url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn resolved_internal_hosts_are_rejected() {
+        let name: Name = "localhost".parse().unwrap();
+        assert!(PublicResolver.resolve(name).await.is_err());
+    }
+}